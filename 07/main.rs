@@ -1,6 +1,8 @@
 use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -29,8 +31,8 @@ fn parse_input(file_name: impl AsRef<Path>) -> Dependencies {
             caps[1].chars().next().unwrap(),
             caps[2].chars().next().unwrap(),
         );
-        deps.entry(b).or_insert_with(Vec::new).push(a);
-        deps.entry(a).or_insert_with(Vec::new);
+        deps.entry(b).or_default().push(a);
+        deps.entry(a).or_default();
     }
     deps
 }
@@ -63,69 +65,256 @@ fn solve_part1(deps: &Dependencies) -> String {
     result
 }
 
-fn solve_part2(deps: &Dependencies) -> usize {
-    let mut deps = deps.clone();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Task {
+    worker: usize,
+    step: char,
+    start: usize,
+    end: usize,
+}
+
+fn schedule_with(
+    deps: &Dependencies,
+    num_workers: usize,
+    duration: impl Fn(char) -> usize,
+) -> Vec<Task> {
+    let mut dependents: HashMap<char, Vec<char>> = HashMap::new();
+    let mut indegree = HashMap::new();
+    for (&step, prereqs) in deps {
+        indegree.insert(step, prereqs.len());
+        for &prereq in prereqs {
+            dependents.entry(prereq).or_default().push(step);
+        }
+    }
+
+    let mut ready = indegree
+        .iter()
+        .filter(|(_, &n)| n == 0)
+        .map(|(&k, _)| k)
+        .collect::<BTreeSet<_>>();
+    let mut idle = (0..num_workers).collect::<BTreeSet<_>>();
+    let mut running = BinaryHeap::new();
+    let mut tasks = vec![];
     let mut secs = 0;
-    let mut steps_done = HashSet::new();
-    let mut workers: [Option<(char, usize)>; NUM_WORKS] = [None; NUM_WORKS];
 
-    while steps_done.len() != deps.len() {
-        let mut candidates = deps
-            .iter()
-            .filter(|(_, v)| v.is_empty())
-            .filter(|(k, _)| !steps_done.contains(*k))
-            .filter(|(&k, _)| !workers.iter().filter_map(|&x| x).any(|x| x.0 == k))
-            .map(|(k, _)| k)
-            .cloned()
-            .collect::<Vec<_>>();
+    loop {
+        while !ready.is_empty() && !idle.is_empty() {
+            let step = ready.pop_first().unwrap();
+            let worker = idle.pop_first().unwrap();
+            let end = secs + duration(step);
+            running.push(Reverse((end, worker, step)));
+            tasks.push(Task {
+                worker,
+                step,
+                start: secs,
+                end,
+            });
+        }
 
-        candidates.sort_unstable();
+        match running.peek() {
+            Some(&Reverse((end, _, _))) => secs = end,
+            None => break,
+        }
 
-        let mut iter = workers.iter_mut().filter(|x| x.is_none());
-        for candidate in candidates {
-            if let Some(worker) = iter.next() {
-                *worker = Some((candidate, step_seconds(candidate)));
+        while let Some(&Reverse((end, worker, step))) = running.peek() {
+            if end != secs {
+                break;
+            }
+            running.pop();
+            idle.insert(worker);
+            for &next in dependents.get(&step).into_iter().flatten() {
+                let n = indegree.get_mut(&next).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.insert(next);
+                }
             }
         }
+    }
 
-        secs += 1;
+    tasks
+}
 
-        for worker in workers.iter_mut() {
-            match worker {
-                Some((step, 1)) => {
-                    for v in deps.values_mut() {
-                        v.retain(|x| x != step);
-                    }
-                    steps_done.insert(*step);
-                    *worker = None;
-                }
-                Some((_, counter)) => {
-                    *counter -= 1;
-                }
-                _ => (),
+fn schedule(deps: &Dependencies) -> Vec<Task> {
+    schedule_with(deps, NUM_WORKS, step_seconds)
+}
+
+fn solve_part2(deps: &Dependencies) -> usize {
+    schedule(deps).iter().map(|t| t.end).max().unwrap_or(0)
+}
+
+fn critical_path(deps: &Dependencies) -> Vec<char> {
+    fn finish_time(step: char, deps: &Dependencies, memo: &mut HashMap<char, usize>) -> usize {
+        if let Some(&t) = memo.get(&step) {
+            return t;
+        }
+        let t = deps[&step]
+            .iter()
+            .map(|&p| finish_time(p, deps, memo))
+            .max()
+            .unwrap_or(0)
+            + step_seconds(step);
+        memo.insert(step, t);
+        t
+    }
+
+    let mut memo = HashMap::new();
+    let mut steps = deps.keys().cloned().collect::<Vec<_>>();
+    steps.sort_unstable();
+
+    let mut path = vec![];
+    let mut current = steps
+        .iter()
+        .cloned()
+        .max_by_key(|&s| (finish_time(s, deps, &mut memo), std::cmp::Reverse(s)));
+
+    while let Some(step) = current {
+        path.push(step);
+        let mut prereqs = deps[&step].clone();
+        prereqs.sort_unstable();
+        current = prereqs
+            .into_iter()
+            .max_by_key(|&p| (finish_time(p, deps, &mut memo), std::cmp::Reverse(p)));
+    }
+
+    path.reverse();
+    path
+}
+
+fn to_dot(deps: &Dependencies) -> String {
+    let path = critical_path(deps);
+    let on_path = |a: char, b: char| path.windows(2).any(|w| w[0] == a && w[1] == b);
+
+    let mut steps = deps.keys().cloned().collect::<Vec<_>>();
+    steps.sort_unstable();
+
+    let mut dot = String::from("digraph steps {\n");
+    for &step in &steps {
+        let secs = step_seconds(step);
+        if path.contains(&step) {
+            dot += &format!(
+                "    {} [label=\"{} ({}s)\", color=red];\n",
+                step, step, secs
+            );
+        } else {
+            dot += &format!("    {} [label=\"{} ({}s)\"];\n", step, step, secs);
+        }
+    }
+    for &step in &steps {
+        let mut prereqs = deps[&step].clone();
+        prereqs.sort_unstable();
+        for prereq in prereqs {
+            if on_path(prereq, step) {
+                dot += &format!("    {} -> {} [color=red];\n", prereq, step);
+            } else {
+                dot += &format!("    {} -> {};\n", prereq, step);
             }
         }
     }
+    dot += "}\n";
+    dot
+}
 
-    secs
+fn to_gantt(tasks: &[Task], num_workers: usize, scale: usize) -> String {
+    let total = tasks.iter().map(|t| t.end).max().unwrap_or(0);
+    let width = total.div_ceil(scale);
+    let mut rows = vec![vec!['.'; width]; num_workers];
+
+    for task in tasks {
+        let (from, to) = (task.start / scale, task.end.div_ceil(scale));
+        for c in &mut rows[task.worker][from..to] {
+            *c = task.step;
+        }
+    }
+
+    rows.iter()
+        .enumerate()
+        .map(|(i, row)| format!("Worker {} |{}|\n", i + 1, row.iter().collect::<String>()))
+        .collect()
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} FILE", env::args().next().unwrap());
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 2 && !(args.len() == 3 && (args[2] == "--dot" || args[2] == "--gantt")) {
+        eprintln!("USAGE: {} FILE [--dot|--gantt]", args[0]);
         process::exit(1);
     }
 
-    let deps = parse_input(env::args().nth(1).unwrap());
-    let part1 = solve_part1(&deps);
-    let part2 = solve_part2(&deps);
-    println!("Part 1: {}", part1);
-    println!("Part 2: {}", part2);
+    let deps = parse_input(&args[1]);
+    match args.get(2).map(String::as_str) {
+        Some("--dot") => print!("{}", to_dot(&deps)),
+        Some("--gantt") => print!("{}", to_gantt(&schedule(&deps), NUM_WORKS, 10)),
+        _ => {
+            let part1 = solve_part1(&deps);
+            let part2 = solve_part2(&deps);
+            println!("Part 1: {}", part1);
+            println!("Part 2: {}", part2);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
+
+    fn schedule_ticks(
+        deps: &Dependencies,
+        num_workers: usize,
+        duration: impl Fn(char) -> usize,
+    ) -> Vec<Task> {
+        let mut deps = deps.clone();
+        let mut secs = 0;
+        let mut steps_done = HashSet::new();
+        let mut workers: Vec<Option<(char, usize)>> = vec![None; num_workers];
+        let mut tasks = vec![];
+
+        while steps_done.len() != deps.len() {
+            let mut candidates = deps
+                .iter()
+                .filter(|(_, v)| v.is_empty())
+                .filter(|(k, _)| !steps_done.contains(*k))
+                .filter(|(&k, _)| !workers.iter().filter_map(|&x| x).any(|x| x.0 == k))
+                .map(|(k, _)| k)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable();
+
+            let mut iter = workers.iter_mut().enumerate().filter(|(_, x)| x.is_none());
+            for candidate in candidates {
+                if let Some((i, worker)) = iter.next() {
+                    *worker = Some((candidate, duration(candidate)));
+                    tasks.push(Task {
+                        worker: i,
+                        step: candidate,
+                        start: secs,
+                        end: secs + duration(candidate),
+                    });
+                }
+            }
+
+            secs += 1;
+
+            for worker in workers.iter_mut() {
+                match worker {
+                    Some((step, 1)) => {
+                        for v in deps.values_mut() {
+                            v.retain(|x| x != step);
+                        }
+                        steps_done.insert(*step);
+                        *worker = None;
+                    }
+                    Some((_, counter)) => {
+                        *counter -= 1;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        tasks
+    }
 
     #[test]
     fn test_puzzle_input() {
@@ -136,4 +325,64 @@ mod tests {
         );
         assert_eq!(solve_part2(&deps), 1099);
     }
+
+    #[test]
+    fn test_critical_path() {
+        let deps = parse_input("input.txt");
+        let path = critical_path(&deps);
+        for w in path.windows(2) {
+            assert!(deps[&w[1]].contains(&w[0]));
+        }
+        assert!(deps[&path[0]].is_empty());
+        let length = path.iter().map(|&s| step_seconds(s)).sum::<usize>();
+        assert!(length <= solve_part2(&deps));
+        assert!(to_dot(&deps).contains(&format!("{} -> {} [color=red];", path[0], path[1])));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let deps = parse_input("input.txt");
+        let tasks = schedule(&deps);
+        assert_eq!(tasks.len(), deps.len());
+        for task in &tasks {
+            for prereq in &deps[&task.step] {
+                let prereq = tasks.iter().find(|t| t.step == *prereq).unwrap();
+                assert!(prereq.end <= task.start);
+            }
+        }
+        let gantt = to_gantt(&tasks, NUM_WORKS, 10);
+        assert_eq!(gantt.lines().count(), NUM_WORKS);
+        assert!(gantt.lines().all(|l| l.len() == "Worker 1 ||".len() + 110));
+
+        let tasks = schedule_with(&deps, 8, step_seconds);
+        let gantt = to_gantt(&tasks, 8, 10);
+        assert_eq!(gantt.lines().count(), 8);
+    }
+
+    #[test]
+    fn test_schedule_matches_tick_loop() {
+        let deps = parse_input("input.txt");
+        assert_eq!(
+            schedule(&deps),
+            schedule_ticks(&deps, NUM_WORKS, step_seconds)
+        );
+
+        let durations = [|c| c as usize % 7 + 1, |c| (c as usize * 31) % 13 + 1];
+        for duration in durations.iter() {
+            for num_workers in 1..=6 {
+                assert_eq!(
+                    schedule_with(&deps, num_workers, duration),
+                    schedule_ticks(&deps, num_workers, duration)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_schedule_long_steps() {
+        let deps = parse_input("input.txt");
+        let tasks = schedule_with(&deps, NUM_WORKS, |c| step_seconds(c) * 1_000_000);
+        let end = tasks.iter().map(|t| t.end).max().unwrap();
+        assert_eq!(end, solve_part2(&deps) * 1_000_000);
+    }
 }
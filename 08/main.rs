@@ -1,73 +1,298 @@
 use std::env;
+use std::fmt;
 use std::fs;
+use std::mem;
 use std::path::Path;
 use std::process;
 
-fn parse_input(file_name: impl AsRef<Path>) -> Vec<usize> {
-    fs::read_to_string(file_name)
-        .unwrap()
-        .split_whitespace()
-        .map(|x| x.parse().unwrap())
+fn parse_input(file_name: impl AsRef<Path>) -> Result<Vec<usize>, ParseError> {
+    parse_nums(&fs::read_to_string(file_name).unwrap())
+}
+
+fn parse_nums(text: &str) -> Result<Vec<usize>, ParseError> {
+    text.split_whitespace()
+        .enumerate()
+        .map(|(offset, x)| x.parse().map_err(|_| ParseError::InvalidNumber(offset)))
         .collect()
 }
 
-fn walk_tree_part1(nums: &[usize], mut metadata: &mut Vec<usize>) -> usize {
-    let (num_childs, num_meta) = (nums[0], nums[1]);
-    let mut index = 2;
+#[derive(Debug, PartialEq, Eq)]
+enum ParseError {
+    InvalidNumber(usize),
+    TruncatedHeader(usize),
+    MissingMetadata(usize),
+    TrailingData(usize),
+}
 
-    for _ in 0..num_childs {
-        index += walk_tree_part1(&nums[index..], &mut metadata);
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidNumber(offset) => write!(f, "invalid number at offset {}", offset),
+            ParseError::TruncatedHeader(offset) => {
+                write!(f, "truncated header at offset {}", offset)
+            }
+            ParseError::MissingMetadata(offset) => {
+                write!(f, "missing metadata at offset {}", offset)
+            }
+            ParseError::TrailingData(offset) => write!(f, "trailing data at offset {}", offset),
+        }
     }
+}
 
-    metadata.extend(&nums[index..index + num_meta]);
+#[derive(Default)]
+struct Node {
+    children: Vec<Node>,
+    metadata: Vec<usize>,
+}
 
-    index + num_meta
+// The derived impls would recurse into the children, so compare and print
+// the serialized form instead.
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.serialize() == other.serialize()
+    }
 }
 
-fn walk_tree_part2(nums: &[usize]) -> (usize, usize) {
-    let (num_childs, num_meta) = (nums[0], nums[1]);
-    let (mut index, mut child_sums) = (2, vec![]);
+impl Eq for Node {}
 
-    for _ in 0..num_childs {
-        let (consumed, sum) = walk_tree_part2(&nums[index..]);
-        index += consumed;
-        child_sums.push(sum);
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Node").field(&self.serialize()).finish()
     }
+}
 
-    let sum = if num_childs == 0 {
-        nums[index..].iter().take(num_meta).sum()
-    } else {
-        (index..index + num_meta)
-            .map(|i| nums[i])
-            .filter(|&i| i > 0 && i <= child_sums.len())
-            .map(|i| child_sums[i - 1])
-            .sum()
-    };
+// Dropping the children recursively would overflow the stack on deep trees.
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut stack = mem::take(&mut self.children);
+        while let Some(mut node) = stack.pop() {
+            stack.append(&mut node.children);
+        }
+    }
+}
+
+impl Node {
+    fn parse(nums: &[usize]) -> Result<Self, ParseError> {
+        let header = |index: usize| match nums.get(index..index + 2) {
+            Some(h) => Ok((h[0], h[1])),
+            None => Err(ParseError::TruncatedHeader(index)),
+        };
+
+        let (num_childs, num_meta) = header(0)?;
+        let mut stack = vec![(Node::default(), num_childs, num_meta)];
+        let mut index = 2;
+
+        loop {
+            let top = stack.last_mut().unwrap();
+            if top.1 > 0 {
+                top.1 -= 1;
+                let (num_childs, num_meta) = header(index)?;
+                stack.push((Node::default(), num_childs, num_meta));
+                index += 2;
+                continue;
+            }
+
+            let (mut node, _, num_meta) = stack.pop().unwrap();
+            if nums.len() - index < num_meta {
+                return Err(ParseError::MissingMetadata(index));
+            }
+            node.metadata = nums[index..index + num_meta].to_vec();
+            index += num_meta;
+
+            match stack.last_mut() {
+                Some(parent) => parent.0.children.push(node),
+                None if index != nums.len() => return Err(ParseError::TrailingData(index)),
+                None => return Ok(node),
+            }
+        }
+    }
+
+    fn serialize(&self) -> Vec<usize> {
+        let mut nums = vec![];
+        let mut stack = vec![(self, false)];
+
+        while let Some((node, visited)) = stack.pop() {
+            if visited {
+                nums.extend(&node.metadata);
+            } else {
+                nums.extend(&[node.children.len(), node.metadata.len()]);
+                stack.push((node, true));
+                stack.extend(node.children.iter().rev().map(|child| (child, false)));
+            }
+        }
+
+        nums
+    }
+
+    // Post-order traversal without recursion, handing each node the results
+    // already computed for its children.
+    fn fold<T: Clone>(&self, f: impl FnMut(&Node, Vec<T>) -> T) -> T {
+        self.fold_all(f).swap_remove(0)
+    }
+
+    // Same as `fold`, but returns the result of every node in pre-order.
+    fn fold_all<T: Clone>(&self, mut f: impl FnMut(&Node, Vec<T>) -> T) -> Vec<T> {
+        let mut results = vec![None];
+        let mut stack = vec![(self, 0, vec![])];
+
+        loop {
+            let (node, _, child_results) = stack.last_mut().unwrap();
+            if child_results.len() < node.children.len() {
+                let child = &node.children[child_results.len()];
+                stack.push((child, results.len(), vec![]));
+                results.push(None);
+                continue;
+            }
+
+            let (node, index, child_results) = stack.pop().unwrap();
+            let result = f(node, child_results);
+            if let Some(parent) = stack.last_mut() {
+                parent.2.push(result.clone());
+            }
+            results[index] = Some(result);
+            if stack.is_empty() {
+                return results.into_iter().map(Option::unwrap).collect();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn depth(&self) -> usize {
+        self.fold(|_, depths| 1 + depths.into_iter().max().unwrap_or(0))
+    }
+
+    #[cfg(test)]
+    fn node_count(&self) -> usize {
+        self.fold(|_, counts| 1 + counts.into_iter().sum::<usize>())
+    }
+
+    fn metadata_sum(&self) -> usize {
+        self.fold(|node, sums| {
+            node.metadata.iter().sum::<usize>() + sums.into_iter().sum::<usize>()
+        })
+    }
 
-    (index + num_meta, sum)
+    fn value(&self) -> usize {
+        self.fold(|node, child_values| node.value_from(&child_values))
+    }
+
+    fn value_from(&self, child_values: &[usize]) -> usize {
+        if child_values.is_empty() {
+            self.metadata.iter().sum()
+        } else {
+            self.metadata
+                .iter()
+                .filter(|&&i| i > 0 && i <= child_values.len())
+                .map(|&i| child_values[i - 1])
+                .sum()
+        }
+    }
+
+    // Visits the nodes in pre-order with their label, as in the puzzle
+    // description, their depth and their value.
+    fn walk(&self, mut f: impl FnMut(&Node, &str, usize, usize)) {
+        let values = self.fold_all(|node, child_values| node.value_from(&child_values));
+
+        let mut stack = vec![(self, 0)];
+        let mut count = 0;
+        while let Some((node, depth)) = stack.pop() {
+            f(node, &label(count), depth, values[count]);
+            stack.extend(node.children.iter().rev().map(|child| (child, depth + 1)));
+            count += 1;
+        }
+    }
+
+    fn outline(&self) -> String {
+        let mut text = String::new();
+        self.walk(|node, label, depth, value| {
+            text += &format!(
+                "{}{} {:?} = {}\n",
+                "  ".repeat(depth),
+                label,
+                node.metadata,
+                value
+            );
+        });
+        text
+    }
+
+    fn to_json(&self) -> String {
+        let mut json = String::new();
+        let mut open: Vec<usize> = vec![];
+        self.walk(|node, label, depth, value| {
+            while open.len() > depth {
+                json += "]}";
+                open.pop();
+            }
+            if let Some(siblings) = open.last_mut() {
+                if *siblings > 0 {
+                    json += ",";
+                }
+                *siblings += 1;
+            }
+            json += &format!(
+                "{{\"label\":\"{}\",\"metadata\":{:?},\"value\":{},\"children\":[",
+                label, node.metadata, value
+            );
+            open.push(0);
+        });
+        json += &"]}".repeat(open.len());
+        json
+    }
+
+    // Children are indexed from zero, unlike the metadata entries.
+    #[cfg(test)]
+    fn get(&self, path: &[usize]) -> Option<&Node> {
+        path.iter().try_fold(self, |node, &i| node.children.get(i))
+    }
 }
 
-fn solve_part1(nums: &[usize]) -> usize {
-    let mut metadata = vec![];
-    walk_tree_part1(nums, &mut metadata);
-    metadata.iter().sum()
+// Labels nodes A to Z, then AA, AB and so on.
+fn label(mut index: usize) -> String {
+    let mut label = vec![];
+    loop {
+        label.push((b'A' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    label.iter().rev().collect()
+}
+
+fn solve_part1(tree: &Node) -> usize {
+    tree.metadata_sum()
 }
 
-fn solve_part2(nums: &[usize]) -> usize {
-    walk_tree_part2(nums).1
+fn solve_part2(tree: &Node) -> usize {
+    tree.value()
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} FILE", env::args().next().unwrap());
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 2 && !(args.len() == 3 && (args[2] == "--outline" || args[2] == "--json")) {
+        eprintln!("USAGE: {} FILE [--outline|--json]", args[0]);
         process::exit(1);
     }
 
-    let nums = parse_input(env::args().nth(1).unwrap());
-    let part1 = solve_part1(&nums);
-    let part2 = solve_part2(&nums);
-    println!("Part 1: {}", part1);
-    println!("Part 2: {}", part2);
+    let tree = match parse_input(&args[1]).and_then(|nums| Node::parse(&nums)) {
+        Ok(tree) => tree,
+        Err(err) => {
+            eprintln!("Invalid license file: {}", err);
+            process::exit(1);
+        }
+    };
+
+    match args.get(2).map(String::as_str) {
+        Some("--outline") => print!("{}", tree.outline()),
+        Some("--json") => println!("{}", tree.to_json()),
+        _ => {
+            let part1 = solve_part1(&tree);
+            let part2 = solve_part2(&tree);
+            println!("Part 1: {}", part1);
+            println!("Part 2: {}", part2);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -76,15 +301,85 @@ mod tests {
 
     #[test]
     fn test_puzzle_example() {
-        let nums = parse_input("example.txt");
-        assert_eq!(solve_part1(&nums), 138);
-        assert_eq!(solve_part2(&nums), 66);
+        let tree = Node::parse(&parse_input("example.txt").unwrap()).unwrap();
+        assert_eq!(solve_part1(&tree), 138);
+        assert_eq!(solve_part2(&tree), 66);
     }
 
     #[test]
     fn test_puzzle_input() {
-        let nums = parse_input("input.txt");
-        assert_eq!(solve_part1(&nums), 42472);
-        assert_eq!(solve_part2(&nums), 21810);
+        let tree = Node::parse(&parse_input("input.txt").unwrap()).unwrap();
+        assert_eq!(solve_part1(&tree), 42472);
+        assert_eq!(solve_part2(&tree), 21810);
+    }
+
+    #[test]
+    fn test_tree_queries() {
+        let nums = parse_input("example.txt").unwrap();
+        let tree = Node::parse(&nums).unwrap();
+        assert_eq!(tree.serialize(), nums);
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.node_count(), 4);
+        assert_eq!(tree.get(&[1, 0]).unwrap().metadata, vec![99]);
+        assert_eq!(tree.get(&[1, 0]).unwrap().value(), 99);
+        assert_eq!(tree.get(&[0]).unwrap().value(), 33);
+        assert_eq!(tree.get(&[2]), None);
+        assert_eq!(
+            tree.fold_all(|node, values| node.value_from(&values)),
+            vec![66, 33, 0, 99]
+        );
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        let depth = 1_000_000;
+        let mut nums = vec![];
+        for _ in 1..depth {
+            nums.extend(&[1, 1]);
+        }
+        nums.extend(&[0, 1, 5]);
+        nums.extend(vec![1; depth - 1]);
+
+        let tree = Node::parse(&nums).unwrap();
+        assert_eq!(tree.depth(), depth);
+        assert_eq!(tree.node_count(), depth);
+        assert_eq!(tree.metadata_sum(), depth + 4);
+        assert_eq!(tree.value(), 5);
+        assert_eq!(tree.serialize(), nums);
+        assert_eq!(tree, Node::parse(&nums).unwrap());
+        assert!(format!("{:?}", tree).starts_with("Node([1, 1, 1, 1,"));
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        use ParseError::*;
+        assert_eq!(Node::parse(&[]), Err(TruncatedHeader(0)));
+        assert_eq!(Node::parse(&[2, 3, 0]), Err(TruncatedHeader(2)));
+        assert_eq!(Node::parse(&[1, 1, 0, 3, 1]), Err(MissingMetadata(4)));
+        assert_eq!(Node::parse(&[0, 1, 5, 7]), Err(TrailingData(3)));
+        assert_eq!(parse_nums("0 1 5 abc"), Err(InvalidNumber(3)));
+        assert_eq!(parse_nums("0 -1"), Err(InvalidNumber(1)));
+    }
+
+    #[test]
+    fn test_outline_and_json() {
+        let tree = Node::parse(&parse_input("example.txt").unwrap()).unwrap();
+        assert_eq!(
+            tree.outline(),
+            "A [1, 1, 2] = 66\n  B [10, 11, 12] = 33\n  C [2] = 0\n    D [99] = 99\n"
+        );
+        assert_eq!(
+            tree.to_json(),
+            concat!(
+                r#"{"label":"A","metadata":[1, 1, 2],"value":66,"children":["#,
+                r#"{"label":"B","metadata":[10, 11, 12],"value":33,"children":[]},"#,
+                r#"{"label":"C","metadata":[2],"value":0,"children":["#,
+                r#"{"label":"D","metadata":[99],"value":99,"children":[]}]}]}"#
+            )
+        );
+        assert_eq!(label(25), "Z");
+        assert_eq!(label(26), "AA");
+        assert_eq!(label(27), "AB");
+        assert_eq!(label(26 * 27), "AAA");
     }
 }
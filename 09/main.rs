@@ -1,7 +1,73 @@
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 use std::process;
 
-const SPECIAL_MARBLE: usize = 23;
+#[macro_use]
+extern crate lazy_static;
+
+const PART2_FACTOR: usize = 100;
+
+lazy_static! {
+    static ref RE: Regex = Regex::new(r"(\d+) players; last marble is worth (\d+) points").unwrap();
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    special_marble: usize,
+    removal_offset: usize,
+    insertion_offset: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            special_marble: 23,
+            removal_offset: 7,
+            insertion_offset: 1,
+        }
+    }
+}
+
+trait Ring {
+    fn len(&self) -> usize;
+    fn current(&self) -> usize;
+    // Inserts a marble after the given number of clockwise steps from the
+    // current marble and makes it the current marble.
+    fn insert(&mut self, marble: usize, offset: usize);
+    // Removes the marble the given number of counter-clockwise steps from the
+    // current marble, making the marble clockwise of it the current one.
+    fn remove(&mut self, offset: usize) -> usize;
+    // Lists the marbles clockwise from marble 0, or from the current marble
+    // once 0 has been removed.
+    fn circle(&self) -> Vec<usize>;
+}
+
+fn from_zero(mut circle: Vec<usize>) -> Vec<usize> {
+    if let Some(zero) = circle.iter().position(|&m| m == 0) {
+        circle.rotate_left(zero);
+    }
+    circle
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RingKind {
+    Linked,
+    Compact,
+}
+
+impl RingKind {
+    fn build(self, num_marbles: usize) -> Box<dyn Ring> {
+        match self {
+            RingKind::Linked => Box::new(LinkedRing::new(num_marbles)),
+            RingKind::Compact => Box::new(CompactRing::new(num_marbles)),
+        }
+    }
+}
 
 #[derive(Clone)]
 struct Node {
@@ -15,61 +81,464 @@ impl Node {
     }
 }
 
-fn solve(num_players: usize, num_marbles: usize) -> usize {
-    let mut scores = vec![0; num_players];
-    let mut current = 1;
-    let mut player = 0;
+struct LinkedRing {
+    nodes: Vec<Node>,
+    current: usize,
+    len: usize,
+}
+
+impl LinkedRing {
+    fn new(num_marbles: usize) -> Self {
+        LinkedRing {
+            nodes: vec![Node::new(0, 0); num_marbles + 1],
+            current: 0,
+            len: 1,
+        }
+    }
+}
+
+impl Ring for LinkedRing {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn current(&self) -> usize {
+        self.current
+    }
+
+    fn insert(&mut self, marble: usize, offset: usize) {
+        let nodes = &mut self.nodes;
+        let mut prev = self.current;
+        for _ in 0..offset {
+            prev = nodes[prev].next;
+        }
+        let next = nodes[prev].next;
+        nodes[prev].next = marble;
+        nodes[next].prev = marble;
+        nodes[marble] = Node::new(prev, next);
+        self.current = marble;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, offset: usize) -> usize {
+        let nodes = &mut self.nodes;
+        let mut ptr = self.current;
+        for _ in 0..offset {
+            ptr = nodes[ptr].prev;
+        }
+        let prev = nodes[ptr].prev;
+        let next = nodes[ptr].next;
+        nodes[prev].next = next;
+        nodes[next].prev = prev;
+        self.current = next;
+        self.len -= 1;
+        ptr
+    }
+
+    fn circle(&self) -> Vec<usize> {
+        let mut circle = Vec::with_capacity(self.len);
+        let mut ptr = self.current;
+        for _ in 0..self.len {
+            circle.push(ptr);
+            ptr = self.nodes[ptr].next;
+        }
+        from_zero(circle)
+    }
+}
+
+// Stores only the marbles in the circle, four bytes each, rotating the deque
+// so that the current marble is always at its back.
+struct CompactRing {
+    marbles: VecDeque<u32>,
+}
+
+impl CompactRing {
+    fn new(num_marbles: usize) -> Self {
+        assert!(num_marbles <= u32::MAX as usize);
+        let mut marbles = VecDeque::with_capacity(num_marbles + 1);
+        marbles.push_back(0);
+        CompactRing { marbles }
+    }
+}
+
+impl Ring for CompactRing {
+    fn len(&self) -> usize {
+        self.marbles.len()
+    }
+
+    fn current(&self) -> usize {
+        *self.marbles.back().unwrap() as usize
+    }
+
+    fn insert(&mut self, marble: usize, offset: usize) {
+        let len = self.marbles.len();
+        self.marbles.rotate_left(offset % len);
+        self.marbles.push_back(marble as u32);
+    }
+
+    fn remove(&mut self, offset: usize) -> usize {
+        let len = self.marbles.len();
+        self.marbles.rotate_right(offset % len);
+        let marble = self.marbles.pop_back().unwrap();
+        self.marbles.rotate_left(1);
+        marble as usize
+    }
+
+    fn circle(&self) -> Vec<usize> {
+        let mut circle: Vec<_> = self.marbles.iter().map(|&m| m as usize).collect();
+        circle.rotate_right(1);
+        from_zero(circle)
+    }
+}
+
+struct MarbleGame {
+    rules: Rules,
+    ring: Box<dyn Ring>,
+    last_marble: usize,
+    num_marbles: usize,
+    scores: Vec<usize>,
+    history: Vec<Vec<(usize, usize)>>,
+}
+
+impl MarbleGame {
+    #[cfg(test)]
+    fn new(num_players: usize, num_marbles: usize) -> Self {
+        MarbleGame::with_rules(num_players, num_marbles, Rules::default(), RingKind::Linked)
+    }
+
+    fn with_rules(num_players: usize, num_marbles: usize, rules: Rules, ring: RingKind) -> Self {
+        MarbleGame {
+            rules,
+            ring: ring.build(num_marbles),
+            last_marble: 0,
+            num_marbles,
+            scores: vec![0; num_players],
+            history: vec![vec![]; num_players],
+        }
+    }
+
+    fn player(&self) -> Option<usize> {
+        match self.last_marble {
+            0 => None,
+            marble => Some((marble - 1) % self.scores.len()),
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        self.last_marble == self.num_marbles
+    }
+
+    // Plays the next marble and returns the points scored with it.
+    fn step(&mut self) -> Option<usize> {
+        if self.is_over() {
+            return None;
+        }
 
-    let mut ring = vec![Node::new(0, 0); num_marbles + 1];
-    ring[0] = Node::new(1, 1);
-    ring[1] = Node::new(0, 0);
+        let marble = self.last_marble + 1;
+        let player = (marble - 1) % self.scores.len();
+        let mut points = 0;
 
-    for marble in 2..=num_marbles {
-        if marble % SPECIAL_MARBLE == 0 {
-            let mut ptr = current;
-            for _ in 0..7 {
-                ptr = ring[ptr].prev;
+        if marble.is_multiple_of(self.rules.special_marble) {
+            // With a single marble left there is nothing to remove.
+            points = marble;
+            if self.ring.len() > 1 {
+                points += self.ring.remove(self.rules.removal_offset);
             }
-            let prev = ring[ptr].prev;
-            let next = ring[ptr].next;
-            ring[prev].next = next;
-            ring[next].prev = prev;
-            current = next;
-            scores[player] += marble + ptr;
+            self.scores[player] += points;
+            self.history[player].push((marble, self.scores[player]));
         } else {
-            let prev = ring[current].next;
-            let next = ring[prev].next;
-            ring[prev].next = marble;
-            ring[next].prev = marble;
-            ring[marble] = Node::new(prev, next);
-            current = marble;
+            self.ring.insert(marble, self.rules.insertion_offset);
         }
-        player = (player + 1) % num_players;
+
+        self.last_marble = marble;
+        Some(points)
+    }
+
+    fn play(&mut self) {
+        while self.step().is_some() {}
+    }
+
+    fn circle(&self) -> Vec<usize> {
+        self.ring.circle()
+    }
+
+    fn current(&self) -> usize {
+        self.ring.current()
+    }
+
+    fn scores(&self) -> &[usize] {
+        &self.scores
     }
 
-    *scores.iter().max().unwrap()
+    // Each entry is the marble that scored and the player's total score after it.
+    #[cfg(test)]
+    fn score_history(&self, player: usize) -> &[(usize, usize)] {
+        &self.history[player]
+    }
+
+    fn winner(&self) -> usize {
+        (0..self.scores.len())
+            .max_by_key(|&i| (self.scores[i], Reverse(i)))
+            .unwrap()
+    }
+}
+
+// Formats the game state as in the turn-by-turn listing from the puzzle.
+impl fmt::Display for MarbleGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.player() {
+            Some(player) => write!(f, "[{}]", player + 1)?,
+            None => write!(f, "[-]")?,
+        }
+        let mut after_current = false;
+        for marble in self.circle() {
+            if marble == self.current() {
+                write!(f, "{:>4}", format!("({})", marble))?;
+                after_current = true;
+            } else if after_current {
+                write!(f, "{:>2}", marble)?;
+                after_current = false;
+            } else {
+                write!(f, "{:>3}", marble)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_game(s: &str) -> (usize, usize) {
+    let caps = RE.captures(s).unwrap();
+    (caps[1].parse().unwrap(), caps[2].parse().unwrap())
+}
+
+fn parse_input(file_name: impl AsRef<Path>) -> (usize, usize) {
+    parse_game(&fs::read_to_string(file_name).unwrap())
+}
+
+fn solve(num_players: usize, num_marbles: usize, rules: Rules, ring: RingKind) -> usize {
+    let mut game = MarbleGame::with_rules(num_players, num_marbles, rules, ring);
+    game.play();
+    game.scores()[game.winner()]
 }
 
 fn main() {
-    if env::args().count() != 3 {
+    let mut args = env::args().collect::<Vec<_>>();
+    let ring = match args.iter().position(|arg| arg == "--compact") {
+        Some(i) => {
+            args.remove(i);
+            RingKind::Compact
+        }
+        None => RingKind::Linked,
+    };
+
+    if args.len() != 2 && args.len() != 5 {
         eprintln!(
-            "USAGE: {} NUM_PLAYERS NUM_MARBLES",
-            env::args().next().unwrap()
+            "USAGE: {} FILE [SPECIAL_MARBLE REMOVAL_OFFSET INSERTION_OFFSET] [--compact]",
+            args[0]
         );
         process::exit(1);
     }
-    let num_players = env::args().nth(1).unwrap().parse().unwrap();
-    let num_marbles = env::args().nth(2).unwrap().parse().unwrap();
-    let score = solve(num_players, num_marbles);
-    println!("Score: {}", score);
+
+    let mut rules = Rules::default();
+    if args.len() == 5 {
+        rules.special_marble = args[2].parse().unwrap();
+        rules.removal_offset = args[3].parse().unwrap();
+        rules.insertion_offset = args[4].parse().unwrap();
+    }
+
+    let (num_players, num_marbles) = parse_input(&args[1]);
+    let part1 = solve(num_players, num_marbles, rules, ring);
+    let part2 = solve(num_players, num_marbles * PART2_FACTOR, rules, ring);
+    println!("Part 1: {}", part1);
+    println!("Part 2: {}", part2);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Instant;
 
     #[test]
     fn test_puzzle_input() {
-        assert_eq!(solve(455, 71223), 384288);
+        let (num_players, num_marbles) = parse_input("input.txt");
+        assert_eq!(
+            solve(num_players, num_marbles, Rules::default(), RingKind::Linked),
+            384288
+        );
+        assert_eq!(
+            solve(
+                num_players,
+                num_marbles * PART2_FACTOR,
+                Rules::default(),
+                RingKind::Linked
+            ),
+            3189426841
+        );
+    }
+
+    #[test]
+    fn test_puzzle_examples() {
+        let examples = [
+            ("9 players; last marble is worth 25 points", 32),
+            ("10 players; last marble is worth 1618 points", 8317),
+            ("13 players; last marble is worth 7999 points", 146373),
+            ("17 players; last marble is worth 1104 points", 2764),
+            ("21 players; last marble is worth 6111 points", 54718),
+            ("30 players; last marble is worth 5807 points", 37305),
+        ];
+        for &(text, score) in examples.iter() {
+            let (num_players, num_marbles) = parse_game(text);
+            assert_eq!(
+                solve(num_players, num_marbles, Rules::default(), RingKind::Linked),
+                score
+            );
+        }
+    }
+
+    #[test]
+    fn test_turn_listing() {
+        let mut game = MarbleGame::new(9, 25);
+        assert_eq!(game.to_string(), "[-] (0)");
+        game.step();
+        assert_eq!(game.to_string(), "[1]  0 (1)");
+        game.step();
+        assert_eq!(game.to_string(), "[2]  0 (2) 1");
+        while game.last_marble < 22 {
+            game.step();
+        }
+        assert_eq!(
+            game.to_string(),
+            "[4]  0 16  8 17  4 18  9 19  2 20 10 21  5(22)11  1 12  6 13  3 14  7 15"
+        );
+        assert_eq!(game.step(), Some(32));
+        assert_eq!(
+            game.to_string(),
+            "[5]  0 16  8 17  4 18(19) 2 20 10 21  5 22 11  1 12  6 13  3 14  7 15"
+        );
+        game.play();
+        assert_eq!(game.step(), None);
+        assert_eq!(game.current(), 25);
+        assert_eq!(game.circle()[..4], [0, 16, 8, 17]);
+        assert_eq!(game.winner(), 4);
+        assert_eq!(game.scores()[4], 32);
+        assert_eq!(game.score_history(4), &[(23, 32)]);
+    }
+
+    fn solve_naive(num_players: usize, num_marbles: usize, rules: Rules) -> usize {
+        let (mut circle, mut current) = (vec![0], 0);
+        let mut scores = vec![0; num_players];
+        for marble in 1..=num_marbles {
+            if marble % rules.special_marble == 0 {
+                let player = (marble - 1) % num_players;
+                if circle.len() == 1 {
+                    scores[player] += marble;
+                } else {
+                    let len = circle.len();
+                    current = (current + len - rules.removal_offset % len) % len;
+                    scores[player] += marble + circle.remove(current);
+                    current %= circle.len();
+                }
+            } else {
+                current = (current + rules.insertion_offset) % circle.len() + 1;
+                circle.insert(current, marble);
+            }
+        }
+        *scores.iter().max().unwrap()
+    }
+
+    #[test]
+    fn test_compact_ring() {
+        let mut linked = MarbleGame::new(9, 25);
+        let mut compact = MarbleGame::with_rules(9, 25, Rules::default(), RingKind::Compact);
+        while linked.step().is_some() {
+            compact.step();
+            assert_eq!(compact.to_string(), linked.to_string());
+        }
+
+        let (num_players, num_marbles) = parse_input("input.txt");
+        assert_eq!(
+            solve(
+                num_players,
+                num_marbles,
+                Rules::default(),
+                RingKind::Compact
+            ),
+            384288
+        );
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_ring_kinds() {
+        for &num_marbles in [10_000_000, 100_000_000].iter() {
+            for &ring in [RingKind::Linked, RingKind::Compact].iter() {
+                let start = Instant::now();
+                let score = solve(455, num_marbles, Rules::default(), ring);
+                println!(
+                    "{:?} ring, {} marbles: {} in {:?}",
+                    ring,
+                    num_marbles,
+                    score,
+                    start.elapsed()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rule_variants() {
+        for special_marble in 1..30 {
+            for removal_offset in 0..10 {
+                for insertion_offset in 0..5 {
+                    let rules = Rules {
+                        special_marble,
+                        removal_offset,
+                        insertion_offset,
+                    };
+                    let expected = solve_naive(7, 500, rules);
+                    assert_eq!(solve(7, 500, rules, RingKind::Linked), expected);
+                    assert_eq!(solve(7, 500, rules, RingKind::Compact), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_marble_zero_removed() {
+        let rules = Rules {
+            special_marble: 2,
+            removal_offset: 1,
+            insertion_offset: 1,
+        };
+        let mut linked = MarbleGame::with_rules(3, 10, rules, RingKind::Linked);
+        let mut compact = MarbleGame::with_rules(3, 10, rules, RingKind::Compact);
+        for _ in 0..2 {
+            linked.step();
+            compact.step();
+        }
+        assert_eq!(linked.to_string(), "[2] (1)");
+        while linked.step().is_some() {
+            compact.step();
+            assert_eq!(compact.to_string(), linked.to_string());
+        }
+        assert_eq!(linked.to_string(), "[1] (9)");
+
+        for special_marble in 2..8 {
+            for removal_offset in 0..6 {
+                let rules = Rules {
+                    special_marble,
+                    removal_offset,
+                    insertion_offset: 1,
+                };
+                let mut linked = MarbleGame::with_rules(3, 50, rules, RingKind::Linked);
+                let mut compact = MarbleGame::with_rules(3, 50, rules, RingKind::Compact);
+                while linked.step().is_some() {
+                    compact.step();
+                    assert_eq!(linked.circle().len(), linked.ring.len());
+                    assert_eq!(compact.to_string(), linked.to_string());
+                }
+            }
+        }
     }
 }
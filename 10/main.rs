@@ -13,6 +13,118 @@ lazy_static! {
         Regex::new(r"position=<\s*(-?\d+),\s*(-?\d+)> velocity=<\s*(-?\d+),\s*(-?\d+)>").unwrap();
 }
 
+const GLYPH_WIDTH: usize = 6;
+const GLYPH_HEIGHT: usize = 10;
+const GLYPH_SPACING: usize = 2;
+
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 15] = [
+    (
+        'A',
+        [
+            "  ##  ", " #  # ", "#    #", "#    #", "#    #", "######", "#    #", "#    #",
+            "#    #", "#    #",
+        ],
+    ),
+    (
+        'B',
+        [
+            "##### ", "#    #", "#    #", "#    #", "##### ", "#    #", "#    #", "#    #",
+            "#    #", "##### ",
+        ],
+    ),
+    (
+        'C',
+        [
+            " #### ", "#    #", "#     ", "#     ", "#     ", "#     ", "#     ", "#     ",
+            "#    #", " #### ",
+        ],
+    ),
+    (
+        'E',
+        [
+            "######", "#     ", "#     ", "#     ", "##### ", "#     ", "#     ", "#     ",
+            "#     ", "######",
+        ],
+    ),
+    (
+        'F',
+        [
+            "######", "#     ", "#     ", "#     ", "##### ", "#     ", "#     ", "#     ",
+            "#     ", "#     ",
+        ],
+    ),
+    (
+        'G',
+        [
+            " #### ", "#    #", "#     ", "#     ", "#     ", "#  ###", "#    #", "#    #",
+            "#   ##", " ### #",
+        ],
+    ),
+    (
+        'H',
+        [
+            "#    #", "#    #", "#    #", "#    #", "######", "#    #", "#    #", "#    #",
+            "#    #", "#    #",
+        ],
+    ),
+    (
+        'J',
+        [
+            "   ###", "    # ", "    # ", "    # ", "    # ", "    # ", "    # ", "#   # ",
+            "#   # ", " ###  ",
+        ],
+    ),
+    (
+        'K',
+        [
+            "#    #", "#   # ", "#  #  ", "# #   ", "##    ", "##    ", "# #   ", "#  #  ",
+            "#   # ", "#    #",
+        ],
+    ),
+    (
+        'L',
+        [
+            "#     ", "#     ", "#     ", "#     ", "#     ", "#     ", "#     ", "#     ",
+            "#     ", "######",
+        ],
+    ),
+    (
+        'N',
+        [
+            "#    #", "##   #", "##   #", "# #  #", "# #  #", "#  # #", "#  # #", "#   ##",
+            "#   ##", "#    #",
+        ],
+    ),
+    (
+        'P',
+        [
+            "##### ", "#    #", "#    #", "#    #", "##### ", "#     ", "#     ", "#     ",
+            "#     ", "#     ",
+        ],
+    ),
+    (
+        'R',
+        [
+            "##### ", "#    #", "#    #", "#    #", "##### ", "#  #  ", "#   # ", "#   # ",
+            "#    #", "#    #",
+        ],
+    ),
+    (
+        'X',
+        [
+            "#    #", "#    #", " #  # ", " #  # ", "  ##  ", "  ##  ", " #  # ", " #  # ",
+            "#    #", "#    #",
+        ],
+    ),
+    (
+        'Z',
+        [
+            "######", "     #", "     #", "    # ", "   #  ", "  #   ", " #    ", "#     ",
+            "#     ", "######",
+        ],
+    ),
+];
+
 #[derive(Clone)]
 struct Point {
     px: isize,
@@ -36,9 +148,9 @@ impl FromStr for Point {
 }
 
 impl Point {
-    fn do_move(&mut self) {
-        self.px += self.vx;
-        self.py += self.vy;
+    fn do_move(&mut self, secs: isize) {
+        self.px += self.vx * secs;
+        self.py += self.vy * secs;
     }
 }
 
@@ -50,50 +162,182 @@ fn parse_input(file_name: impl AsRef<Path>) -> Vec<Point> {
         .collect()
 }
 
-fn message_received(points: &[Point]) -> bool {
+fn bounding_box(points: &[Point]) -> (isize, isize, isize, isize) {
     let min_x = points.iter().map(|p| p.px).min().unwrap();
     let max_x = points.iter().map(|p| p.px).max().unwrap();
     let min_y = points.iter().map(|p| p.py).min().unwrap();
     let max_y = points.iter().map(|p| p.py).max().unwrap();
-    let width = (max_x - min_x + 1) as usize;
-    let height = (max_y - min_y + 1) as usize;
+    (min_x, min_y, max_x, max_y)
+}
 
-    if height > 10 {
-        return false;
+fn area_at(points: &[Point], secs: isize) -> isize {
+    let mut points = points.to_vec();
+    for p in points.iter_mut() {
+        p.do_move(secs);
     }
+    let (min_x, min_y, max_x, max_y) = bounding_box(&points);
+    (max_x - min_x + 1) * (max_y - min_y + 1)
+}
+
+fn render(points: &[Point]) -> String {
+    let (min_x, min_y, max_x, max_y) = bounding_box(points);
+    let width = (max_x - min_x + 1) as usize;
+    let height = (max_y - min_y + 1) as usize;
 
     let mut grid = vec![vec![' '; width]; height];
     for p in points {
         grid[(p.py - min_y) as usize][(p.px - min_x) as usize] = '#';
     }
 
-    for row in &grid {
-        println!("{}", row.iter().collect::<String>());
+    grid.iter()
+        .map(|row| row.iter().collect::<String>() + "\n")
+        .collect()
+}
+
+// Unknown glyphs are decoded as '?'.
+fn ocr(points: &[Point]) -> String {
+    let text = render(points);
+    let grid = text.lines().collect::<Vec<_>>();
+    if grid.len() != GLYPH_HEIGHT {
+        return String::from("?");
     }
-    println!();
 
-    true
+    let stride = GLYPH_WIDTH + GLYPH_SPACING;
+    let num_glyphs = grid[0].len().div_ceil(stride);
+    (0..num_glyphs)
+        .map(|i| {
+            let x = i * stride;
+            GLYPHS
+                .iter()
+                .find(|(_, rows)| {
+                    rows.iter().zip(&grid).all(|(glyph_row, row)| {
+                        let row = format!("{:width$}", row, width = x + GLYPH_WIDTH);
+                        &row[x..x + GLYPH_WIDTH] == *glyph_row
+                    })
+                })
+                .map_or('?', |&(c, _)| c)
+        })
+        .collect()
 }
 
-fn solve(points: &mut [Point]) -> usize {
-    let mut secs = 0;
-    while !message_received(&points) {
+// Returns the points at every second from `window` seconds before the given
+// time until `window` seconds after it.
+fn frames(points: &[Point], secs: isize, window: isize) -> Vec<(isize, Vec<Point>)> {
+    let mut points = points.to_vec();
+    for p in points.iter_mut() {
+        p.do_move(-window);
+    }
+
+    let mut frames = vec![(secs - window, points.clone())];
+    for t in secs - window + 1..=secs + window {
         for p in points.iter_mut() {
-            p.do_move();
+            p.do_move(1);
+        }
+        frames.push((t, points.clone()));
+    }
+    frames
+}
+
+fn to_ppm(points: &[Point], bbox: (isize, isize, isize, isize)) -> Vec<u8> {
+    let (min_x, min_y, max_x, max_y) = bbox;
+    let width = (max_x - min_x + 1) as usize;
+    let height = (max_y - min_y + 1) as usize;
+
+    let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    let header_len = ppm.len();
+    ppm.resize(header_len + width * height * 3, 0);
+    for p in points {
+        let i = header_len + ((p.py - min_y) as usize * width + (p.px - min_x) as usize) * 3;
+        ppm[i..i + 3].copy_from_slice(&[255, 255, 255]);
+    }
+    ppm
+}
+
+// All the frames share the bounding box of the whole animation.
+fn export_frames(dir: impl AsRef<Path>, frames: &[(isize, Vec<Point>)]) {
+    let all_points = frames
+        .iter()
+        .flat_map(|(_, points)| points.iter().cloned())
+        .collect::<Vec<_>>();
+    let bbox = bounding_box(&all_points);
+
+    fs::create_dir_all(&dir).unwrap();
+    for (i, (_, points)) in frames.iter().enumerate() {
+        let file_name = dir.as_ref().join(format!("frame-{:04}.ppm", i));
+        fs::write(file_name, to_ppm(points, bbox)).unwrap();
+    }
+}
+
+// The bounding box shrinks while the points converge and grows afterwards, so
+// its area can be minimised with a ternary search once an upper bound for the
+// time is found.
+fn solve(points: &[Point]) -> (usize, Vec<Point>) {
+    let mut hi = 1;
+    while area_at(points, 2 * hi) < area_at(points, hi) {
+        hi *= 2;
+    }
+
+    let (mut lo, mut hi) = (0, 2 * hi);
+    while hi - lo > 2 {
+        let m1 = lo + (hi - lo) / 3;
+        let m2 = hi - (hi - lo) / 3;
+        if area_at(points, m1) < area_at(points, m2) {
+            hi = m2 - 1;
+        } else if area_at(points, m1) > area_at(points, m2) {
+            lo = m1 + 1;
+        } else {
+            lo = m1;
+            hi = m2;
         }
-        secs += 1;
     }
-    secs
+
+    let secs = (lo..=hi).min_by_key(|&t| area_at(points, t)).unwrap();
+    let mut points = points.to_vec();
+    for p in points.iter_mut() {
+        p.do_move(secs);
+    }
+    (secs as usize, points)
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} FILE", env::args().next().unwrap());
+    let args = env::args().collect::<Vec<_>>();
+    let valid = match args.len() {
+        2 => true,
+        4 => args[2] == "--play",
+        5 => args[2] == "--frames",
+        _ => false,
+    };
+    if !valid {
+        eprintln!(
+            "USAGE: {} FILE [--play WINDOW | --frames WINDOW DIR]",
+            args[0]
+        );
         process::exit(1);
     }
 
-    let mut points = parse_input(env::args().nth(1).unwrap());
-    let secs = solve(&mut points);
+    let points = parse_input(&args[1]);
+    let (secs, points) = solve(&points);
+
+    if args.len() > 2 {
+        let window = args[3].parse().unwrap();
+        let frames = frames(&points, secs as isize, window);
+        if args[2] == "--play" {
+            for (t, points) in &frames {
+                println!("Second {}:", t);
+                print!("{}", render(points));
+                println!();
+            }
+        } else {
+            export_frames(&args[4], &frames);
+        }
+        return;
+    }
+
+    let message = ocr(&points);
+    if message.contains('?') {
+        print!("{}", render(&points));
+    }
+    println!("Message: {}", message);
     println!("Seconds: {}", secs);
 }
 
@@ -103,7 +347,33 @@ mod tests {
 
     #[test]
     fn test_puzzle_input() {
-        let mut points = parse_input("input.txt");
-        assert_eq!(solve(&mut points), 10867);
+        let points = parse_input("input.txt");
+        let (secs, points) = solve(&points);
+        assert_eq!(secs, 10867);
+        assert_eq!(ocr(&points), "FPZKLJZG");
+    }
+
+    #[test]
+    fn test_frames() {
+        let points = parse_input("input.txt");
+        let (secs, converged) = solve(&points);
+        let frames = frames(&converged, secs as isize, 3);
+        assert_eq!(frames.len(), 7);
+        assert_eq!(frames[0].0, secs as isize - 3);
+        assert_eq!(frames[3].0, secs as isize);
+        assert_eq!(render(&frames[3].1), render(&converged));
+
+        let mut rewound = points.clone();
+        for p in rewound.iter_mut() {
+            p.do_move(secs as isize - 3);
+        }
+        assert_eq!(render(&frames[0].1), render(&rewound));
+
+        let dir = env::temp_dir().join(format!("aoc-2018-day-10-frames-{}", process::id()));
+        export_frames(&dir, &frames);
+        let ppm = fs::read(dir.join("frame-0003.ppm")).unwrap();
+        assert!(ppm.starts_with(b"P6\n"));
+        assert!(!dir.join("frame-0007.ppm").exists());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,4 +1,8 @@
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::env;
+use std::ops::RangeInclusive;
 use std::process;
 
 const WIDTH: usize = 300;
@@ -10,48 +14,202 @@ fn power_level(x: usize, y: usize, serial_number: usize) -> isize {
     hundreds_digit((rack_id * y + serial_number) * rack_id) as isize - 5
 }
 
-fn solve_part1(serial_number: usize) -> (usize, usize) {
-    (0..HEIGHT - 1)
-        .flat_map(|sr| (0..WIDTH - 1).map(move |sc| (sr, sc)))
-        .max_by_key(|&(sr, sc)| {
-            (0..3)
-                .flat_map(|r| (0..3).map(move |c| (r, c)))
-                .map(|(r, c)| power_level(sc + c, sr + r, serial_number))
-                .sum::<isize>()
-        })
-        .map(|(r, c)| (c, r))
-        .unwrap()
+// Cells are addressed from (1, 1) to (width, height).
+struct PowerGrid {
+    width: usize,
+    height: usize,
+    sat: Vec<isize>,
+    max_cell: isize,
 }
 
-fn solve_part2(serial_number: usize) -> (usize, usize, usize) {
-    let mut sat = [[0; WIDTH + 1]; HEIGHT + 1];
-
-    // https://en.wikipedia.org/wiki/Summed-area_table
-    for r in 1..=HEIGHT {
-        for c in 1..=WIDTH {
-            sat[r][c] = power_level(c, r, serial_number);
-            sat[r][c] += sat[r - 1][c];
-            sat[r][c] += sat[r][c - 1];
-            sat[r][c] -= sat[r - 1][c - 1];
+impl PowerGrid {
+    fn new(width: usize, height: usize, cell_value: impl Fn(usize, usize) -> isize) -> Self {
+        let mut sat = vec![0; (width + 1) * (height + 1)];
+        let idx = |x: usize, y: usize| y * (width + 1) + x;
+        let mut max_cell = isize::MIN;
+
+        // https://en.wikipedia.org/wiki/Summed-area_table
+        for y in 1..=height {
+            for x in 1..=width {
+                let value = cell_value(x, y);
+                max_cell = max_cell.max(value);
+                sat[idx(x, y)] = value;
+                sat[idx(x, y)] += sat[idx(x, y - 1)];
+                sat[idx(x, y)] += sat[idx(x - 1, y)];
+                sat[idx(x, y)] -= sat[idx(x - 1, y - 1)];
+            }
+        }
+
+        PowerGrid {
+            width,
+            height,
+            sat,
+            max_cell,
         }
     }
 
-    (0..=WIDTH)
-        .flat_map(|size| {
-            (1..=HEIGHT - size).flat_map(move |r| (1..=WIDTH - size).map(move |c| (r, c, size)))
-        })
-        .max_by_key(|&(r, c, s)| sat[r + s][c + s] - sat[r][c + s] - sat[r + s][c] + sat[r][c])
-        .map(|(r, c, s)| (c + 1, r + 1, s))
-        .unwrap()
+    fn sat(&self, x: usize, y: usize) -> isize {
+        self.sat[y * (self.width + 1) + x]
+    }
+
+    fn square_power(&self, x: usize, y: usize, size: usize) -> isize {
+        let (x, y) = (x - 1, y - 1);
+        self.sat(x + size, y + size) - self.sat(x, y + size) - self.sat(x + size, y)
+            + self.sat(x, y)
+    }
+
+    fn squares(&self, size: usize) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        let (width, height) = (self.width, self.height);
+        (1..(height + 2).saturating_sub(size))
+            .flat_map(move |y| (1..(width + 2).saturating_sub(size)).map(move |x| (x, y, size)))
+    }
+
+    // Returns the top-left corner of the square and its total power.
+    fn best_square(&self, size: usize) -> Option<(usize, usize, isize)> {
+        self.best_square_in(size..=size)
+            .map(|(x, y, _, power)| (x, y, power))
+    }
+
+    fn sizes(&self, sizes: RangeInclusive<usize>) -> RangeInclusive<usize> {
+        let max_size = self.width.min(self.height);
+        (*sizes.start()).max(1)..=(*sizes.end()).min(max_size)
+    }
+
+    // Returns the top-left corner of the square, its size and its total power.
+    // Ties are ranked as `top_squares` does.
+    fn best_square_in(&self, sizes: RangeInclusive<usize>) -> Option<(usize, usize, usize, isize)> {
+        self.best_square_by_size(sizes)
+            .into_iter()
+            .max_by_key(|&(_, _, size, power)| (power, size))
+    }
+
+    // Returns the best square of every size that was actually searched. The
+    // sizes are searched in parallel batches. An s-square is an (s-1)-square
+    // plus a border of 2s-1 cells, so a size is skipped when that bound cannot
+    // beat the best square found so far.
+    fn best_square_by_size(
+        &self,
+        sizes: RangeInclusive<usize>,
+    ) -> Vec<(usize, usize, usize, isize)> {
+        let (mut size, last) = self.sizes(sizes).into_inner();
+        let mut found = vec![];
+        let mut best = isize::MIN;
+        // Upper bound on the power of the squares one size below `size`.
+        let mut bound: Option<isize> = None;
+
+        while size <= last {
+            let batch_start = size;
+            let mut batch = vec![];
+            match bound {
+                // The first size has nothing to be bounded by.
+                None => {
+                    batch.push(size);
+                    size += 1;
+                }
+                Some(mut chained) => {
+                    while size <= last && batch.len() < rayon::current_num_threads() {
+                        chained = chained.saturating_add(self.border_bound(size));
+                        // A larger size wins a tie, so only a lower bound rules it out.
+                        if chained >= best {
+                            batch.push(size);
+                        } else if self.max_cell <= 0 {
+                            // The bound never grows again, so no larger size can win.
+                            return found;
+                        }
+                        size += 1;
+                    }
+                }
+            }
+
+            let mut searched: Vec<_> = batch
+                .par_iter()
+                .filter_map(|&size| {
+                    self.squares(size)
+                        .map(|(x, y, size)| (x, y, size, self.square_power(x, y, size)))
+                        .max_by_key(|&(_, _, _, power)| power)
+                })
+                .collect();
+
+            // Tighten the bound with the exact power of the sizes searched.
+            for s in batch_start..size {
+                bound = match searched.iter().find(|square| square.2 == s) {
+                    Some(square) => Some(square.3),
+                    None => bound.map(|b| b.saturating_add(self.border_bound(s))),
+                };
+            }
+            best = searched
+                .iter()
+                .map(|square| square.3)
+                .fold(best, isize::max);
+            found.append(&mut searched);
+        }
+
+        found
+    }
+
+    fn border_bound(&self, size: usize) -> isize {
+        ((2 * size - 1) as isize).saturating_mul(self.max_cell)
+    }
+
+    // Squares with the same total power are ranked as the last one found when
+    // scanning by size, then by row and then by column: the largest first,
+    // then the bottom-most and then the right-most.
+    fn top_squares(
+        &self,
+        sizes: RangeInclusive<usize>,
+        n: usize,
+    ) -> Vec<(usize, usize, usize, isize)> {
+        let sizes = self.sizes(sizes);
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        let squares = sizes.flat_map(|size| self.squares(size)).enumerate();
+        for (seq, (x, y, size)) in squares {
+            heap.push((
+                Reverse(self.square_power(x, y, size)),
+                Reverse(seq),
+                x,
+                y,
+                size,
+            ));
+            if heap.len() > n {
+                heap.pop();
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|(Reverse(power), _, x, y, size)| (x, y, size, power))
+            .collect()
+    }
+}
+
+fn solve_part1(serial_number: usize) -> (usize, usize) {
+    let grid = PowerGrid::new(WIDTH, HEIGHT, |x, y| power_level(x, y, serial_number));
+    let (x, y, _) = grid.best_square(3).unwrap();
+    (x, y)
+}
+
+fn solve_part2(serial_number: usize) -> (usize, usize, usize) {
+    let grid = PowerGrid::new(WIDTH, HEIGHT, |x, y| power_level(x, y, serial_number));
+    let (x, y, size, _) = grid.best_square_in(1..=WIDTH).unwrap();
+    (x, y, size)
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} SERIAL_NUMBER", env::args().next().unwrap());
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 2 && !(args.len() == 4 && args[2] == "--top") {
+        eprintln!("USAGE: {} SERIAL_NUMBER [--top N]", args[0]);
         process::exit(1);
     }
 
-    let serial_number = env::args().nth(1).unwrap().parse().unwrap();
+    let serial_number = args[1].parse().unwrap();
+    if args.len() == 4 {
+        let grid = PowerGrid::new(WIDTH, HEIGHT, |x, y| power_level(x, y, serial_number));
+        for (x, y, size, power) in grid.top_squares(1..=WIDTH, args[3].parse().unwrap()) {
+            println!("{},{},{}: {}", x, y, size, power);
+        }
+        return;
+    }
+
     let part1 = solve_part1(serial_number);
     let part2 = solve_part2(serial_number);
     println!("Part 1: {},{}", part1.0, part1.1);
@@ -62,9 +220,117 @@ fn main() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_puzzle_examples() {
+        assert_eq!(power_level(3, 5, 8), 4);
+        assert_eq!(power_level(122, 79, 57), -5);
+        assert_eq!(power_level(217, 196, 39), 0);
+        assert_eq!(power_level(101, 153, 71), 4);
+
+        let grid = PowerGrid::new(WIDTH, HEIGHT, |x, y| power_level(x, y, 18));
+        assert_eq!(grid.best_square(3), Some((33, 45, 29)));
+        assert_eq!(grid.best_square_in(1..=WIDTH), Some((90, 269, 16, 113)));
+
+        let grid = PowerGrid::new(WIDTH, HEIGHT, |x, y| power_level(x, y, 42));
+        assert_eq!(grid.best_square(3), Some((21, 61, 30)));
+        assert_eq!(grid.best_square_in(1..=WIDTH), Some((232, 251, 12, 119)));
+
+        let top = grid.top_squares(3..=3, 5);
+        assert_eq!(top.len(), 5);
+        assert_eq!(top[0], (21, 61, 3, 30));
+        assert!(top.windows(2).all(|w| w[0].3 >= w[1].3));
+    }
+
     #[test]
     fn test_puzzle_input() {
         assert_eq!(solve_part1(8141), (235, 16));
         assert_eq!(solve_part2(8141), (236, 227, 14));
     }
+
+    #[test]
+    fn test_power_grid() {
+        let grid = PowerGrid::new(4, 3, |x, y| {
+            if x == 1 || y == 3 {
+                -1
+            } else {
+                x as isize * y as isize
+            }
+        });
+        assert_eq!(grid.square_power(2, 1, 2), 2 + 3 + 4 + 6);
+        assert_eq!(grid.best_square(1), Some((4, 2, 8)));
+        assert_eq!(grid.best_square(2), Some((3, 1, 21)));
+        assert_eq!(grid.best_square(3), Some((2, 1, 24)));
+        assert_eq!(grid.best_square(4), None);
+        assert_eq!(grid.best_square_in(1..=2), Some((3, 1, 2, 21)));
+        assert_eq!(grid.best_square_in(3..=10), Some((2, 1, 3, 24)));
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        for seed in 0..20 {
+            let cell_value = |x: usize, y: usize| {
+                ((x * 7 + y * 13 + seed * 31) % 5) as isize - 3 - seed as isize / 10
+            };
+            let grid = PowerGrid::new(40, 30, cell_value);
+            assert_eq!(
+                grid.best_square_in(1..=40),
+                grid.top_squares(1..=40, 1).pop()
+            );
+            assert_eq!(
+                grid.best_square_in(5..=12),
+                grid.top_squares(5..=12, 1).pop()
+            );
+        }
+    }
+
+    #[test]
+    fn test_ties() {
+        // A single positive cell, covered by squares of every size.
+        let grid = PowerGrid::new(3, 3, |x, y| (x == 2 && y == 2) as isize);
+        assert_eq!(grid.best_square(1), Some((2, 2, 1)));
+        assert_eq!(grid.best_square(2), Some((2, 2, 1)));
+        assert_eq!(grid.best_square_in(1..=3), Some((1, 1, 3, 1)));
+        assert_eq!(
+            grid.top_squares(1..=3, 4),
+            [(1, 1, 3, 1), (2, 2, 2, 1), (1, 2, 2, 1), (2, 1, 2, 1)]
+        );
+
+        // The serial search this replaced kept the last of the best squares.
+        for seed in 0..20 {
+            let grid = PowerGrid::new(12, 12, |x, y| ((x * 3 + y * 5 + seed) % 4) as isize - 2);
+            let serial = (1..=12)
+                .flat_map(|size| grid.squares(size))
+                .map(|(x, y, size)| (x, y, size, grid.square_power(x, y, size)))
+                .max_by_key(|square| square.3);
+            assert_eq!(grid.best_square_in(1..=12), serial);
+            assert_eq!(grid.top_squares(1..=12, 1).pop(), serial);
+        }
+    }
+
+    #[test]
+    fn test_pruned_sizes() {
+        let sizes = |grid: &PowerGrid| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap();
+            pool.install(|| grid.best_square_by_size(1..=20))
+                .iter()
+                .map(|square| square.2)
+                .collect::<Vec<_>>()
+        };
+
+        let grid = PowerGrid::new(20, 20, |x, y| if x <= 2 && y <= 2 { 4 } else { -5 });
+        assert_eq!(sizes(&grid), [1, 2, 3, 4, 5, 8, 9, 14, 15]);
+        assert_eq!(grid.best_square_in(1..=20), Some((1, 1, 2, 16)));
+        assert_eq!(grid.top_squares(1..=20, 1), [(1, 1, 2, 16)]);
+
+        // With only negative cells the search stops after the first size.
+        let grid = PowerGrid::new(20, 20, |x, y| -((x + y) as isize % 3) - 1);
+        assert_eq!(sizes(&grid), [1]);
+        assert_eq!(
+            grid.best_square_in(1..=20),
+            grid.top_squares(1..=20, 1).pop()
+        );
+    }
 }
@@ -1,13 +1,11 @@
-use std::collections::HashMap;
-use std::collections::VecDeque;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::process;
 
 const NUM_GENS_PART1: usize = 20;
 const NUM_GENS_PART2: usize = 50000000000;
-const CONVERGENCE: usize = 10;
 
 type Rule = (Vec<char>, char);
 
@@ -36,68 +34,336 @@ fn parse_input(file_name: impl AsRef<Path>) -> (Vec<char>, Vec<Rule>) {
     (initial_state, rules)
 }
 
-fn rule_matches(rule: &Rule, i: usize, pots: &VecDeque<char>) -> bool {
-    rule.0[0] == pots[i - 2]
-        && rule.0[1] == pots[i - 1]
-        && rule.0[2] == pots[i]
-        && rule.0[3] == pots[i + 1]
-        && rule.0[4] == pots[i + 2]
+// Pots holding plants, kept trimmed so that the first and the last pots in
+// the bitset have a plant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Pots {
+    offset: isize,
+    len: usize,
+    bits: Vec<u64>,
 }
 
-fn calculate_score(pots: &VecDeque<char>, gen: usize) -> isize {
-    pots.iter()
-        .enumerate()
-        .filter(|&(_, &x)| x == '#')
-        .map(|(i, _)| i as isize - (2 + gen as isize))
-        .sum()
+impl Pots {
+    fn from_bits(offset: isize, bits: impl IntoIterator<Item = bool>) -> Self {
+        let mut pots = Pots {
+            offset,
+            len: 0,
+            bits: vec![],
+        };
+        let mut empty = 0;
+
+        for bit in bits {
+            if !bit {
+                if pots.len == 0 {
+                    pots.offset += 1;
+                } else {
+                    empty += 1;
+                }
+                continue;
+            }
+
+            pots.len += empty;
+            empty = 0;
+            pots.bits.resize(pots.len / 64 + 1, 0);
+            pots.bits[pots.len / 64] |= 1 << (pots.len % 64);
+            pots.len += 1;
+        }
+
+        pots
+    }
+
+    fn get(&self, pot: isize) -> bool {
+        let i = pot - self.offset;
+        i >= 0 && (i as usize) < self.len && self.bits[i as usize / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn plants(&self) -> impl Iterator<Item = isize> + '_ {
+        (self.offset..self.offset + self.len as isize).filter(move |&pot| self.get(pot))
+    }
+
+    fn score(&self) -> isize {
+        self.plants().sum()
+    }
 }
 
-fn solve(initial_state: &[char], rules: &[Rule], num_gens: usize) -> isize {
-    let mut pots = VecDeque::new();
-    pots.extend(&['.', '.', '.']);
-    pots.extend(initial_state.iter());
-    pots.extend(&['.', '.', '.']);
+const MAX_RULE_WIDTH: usize = 21;
+
+#[derive(Debug, PartialEq, Eq)]
+enum RuleError {
+    NoRules,
+    InvalidWidth(usize),
+    MixedWidths(usize, usize),
+    // Every empty pot out to infinity would grow a plant.
+    EmptyNeighbourhoodGrows,
+}
 
-    let mut counts = HashMap::new();
-    let mut last_score = 0;
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::NoRules => write!(f, "no rules given"),
+            RuleError::InvalidWidth(width) => write!(
+                f,
+                "rule width {} is not an odd number up to {}",
+                width, MAX_RULE_WIDTH
+            ),
+            RuleError::MixedWidths(a, b) => write!(f, "rules of widths {} and {} mixed", a, b),
+            RuleError::EmptyNeighbourhoodGrows => {
+                write!(f, "empty pots grow plants, filling infinitely many pots")
+            }
+        }
+    }
+}
+
+// Maps the neighbourhood of a pot, read as a binary number with the leftmost
+// pot as the most significant bit, to whether the pot has a plant next.
+// Neighbourhoods without a rule leave the pot empty.
+struct Automaton {
+    radius: usize,
+    table: Vec<Option<bool>>,
+}
+
+impl Automaton {
+    fn new(rules: &[Rule]) -> Result<Self, RuleError> {
+        let width = rules.first().ok_or(RuleError::NoRules)?.0.len();
+        if width % 2 == 0 || width > MAX_RULE_WIDTH {
+            return Err(RuleError::InvalidWidth(width));
+        }
+
+        let mut table = vec![None; 1 << width];
+        for (pattern, result) in rules {
+            if pattern.len() != width {
+                return Err(RuleError::MixedWidths(width, pattern.len()));
+            }
+            let index = pattern
+                .iter()
+                .fold(0, |acc, &c| acc << 1 | (c == '#') as usize);
+            table[index] = Some(*result == '#');
+        }
+
+        if table[0] == Some(true) {
+            return Err(RuleError::EmptyNeighbourhoodGrows);
+        }
+
+        Ok(Automaton {
+            radius: width / 2,
+            table,
+        })
+    }
+
+    fn missing_patterns(&self) -> Vec<String> {
+        let width = 2 * self.radius + 1;
+        (0..self.table.len())
+            .filter(|&index| self.table[index].is_none())
+            .map(|index| {
+                (0..width)
+                    .rev()
+                    .map(|bit| if index & (1 << bit) != 0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn step(&self, pots: &Pots) -> Pots {
+        let radius = self.radius as isize;
+        let mask = self.table.len() - 1;
+        let start = pots.offset - radius;
+        let end = pots.offset + pots.len as isize - 1 + radius;
+
+        let mut window =
+            (start - radius..start + radius).fold(0, |acc, pot| acc << 1 | pots.get(pot) as usize);
+        let bits = (start..=end).map(|pot| {
+            window = (window << 1 | pots.get(pot + radius) as usize) & mask;
+            self.table[window] == Some(true)
+        });
+
+        Pots::from_bits(start, bits)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cycle {
+    start: usize,
+    period: usize,
+    drift: isize,
+}
+
+// Once the trimmed pattern of pots repeats, every following generation is
+// the one a period earlier shifted by the drift, so the remaining generations
+// can be skipped exactly. The repeat is found with Brent's algorithm, which
+// only keeps one earlier generation around.
+fn simulate(automaton: &Automaton, initial: &Pots, num_gens: usize) -> (Pots, Option<Cycle>) {
+    let same_pattern = |a: &Pots, b: &Pots| a.len == b.len && a.bits == b.bits;
+    let mut pots = initial.clone();
+    let mut saved = initial.clone();
+    let (mut power, mut period) = (1, 0);
 
     for gen in 1..=num_gens {
-        let copy = pots.clone();
-        for (i, pot) in pots.iter_mut().enumerate().skip(2).take(copy.len() - 4) {
-            *pot = if let Some(r) = rules.iter().find(|r| rule_matches(r, i, &copy)) {
-                r.1
-            } else {
-                '.'
+        pots = automaton.step(&pots);
+        period += 1;
+        if same_pattern(&pots, &saved) {
+            let drift = pots.offset - saved.offset;
+
+            // The cycle starts at the first generation matching the one a
+            // period later.
+            let mut early = initial.clone();
+            let mut late = initial.clone();
+            for _ in 0..period {
+                late = automaton.step(&late);
             }
+            let mut start = 0;
+            while !same_pattern(&early, &late) {
+                early = automaton.step(&early);
+                late = automaton.step(&late);
+                start += 1;
+            }
+
+            let remaining = num_gens - gen;
+            for _ in 0..remaining % period {
+                pots = automaton.step(&pots);
+            }
+            pots.offset += (remaining / period) as isize * drift;
+            let cycle = Cycle {
+                start,
+                period,
+                drift,
+            };
+            return (pots, Some(cycle));
+        }
+        if period == power {
+            saved = pots.clone();
+            power *= 2;
+            period = 0;
         }
+    }
 
-        let score = calculate_score(&pots, gen);
-        let diff_count = counts.entry(score - last_score).or_insert(0);
+    (pots, None)
+}
 
-        if *diff_count > CONVERGENCE {
-            last_score = (score - last_score) * (num_gens - gen) as isize + score;
-            break;
-        } else {
-            last_score = score;
-            *diff_count += 1;
+fn generations(automaton: &Automaton, initial: &Pots, from: usize, to: usize) -> Vec<Pots> {
+    let mut pots = simulate(automaton, initial, from).0;
+    let mut gens = vec![pots.clone()];
+    for _ in from..to {
+        pots = automaton.step(&pots);
+        gens.push(pots.clone());
+    }
+    gens
+}
+
+// Returns the first and the last pot shown, always including pot 0.
+fn extent(gens: &[Pots]) -> (isize, isize) {
+    gens.iter()
+        .filter(|pots| pots.len > 0)
+        .fold((0, 0), |(lo, hi), pots| {
+            (
+                lo.min(pots.offset),
+                hi.max(pots.offset + pots.len as isize - 1),
+            )
+        })
+}
+
+// Draws the generations starting at `from`, one per line, under a ruler with
+// the number of every tenth pot written vertically.
+fn render(gens: &[Pots], from: usize) -> String {
+    let (lo, hi) = extent(gens);
+    let label_width = (from + gens.len()).saturating_sub(1).to_string().len();
+
+    let labels = (lo..=hi)
+        .map(|pot| match pot % 10 {
+            0 => pot.to_string(),
+            _ => String::new(),
+        })
+        .collect::<Vec<_>>();
+    let ruler_height = labels.iter().map(String::len).max().unwrap();
+
+    let mut text = String::new();
+    for line in 0..ruler_height {
+        text += &" ".repeat(label_width + 2);
+        for label in &labels {
+            let pad = ruler_height - label.len();
+            text.push(if line < pad {
+                ' '
+            } else {
+                label.as_bytes()[line - pad] as char
+            });
         }
+        text = text.trim_end().to_string() + "\n";
+    }
+
+    for (gen, pots) in gens.iter().enumerate() {
+        let row = (lo..=hi)
+            .map(|pot| if pots.get(pot) { '#' } else { '.' })
+            .collect::<String>();
+        text += &format!("{:>width$}: {}\n", from + gen, row, width = label_width);
+    }
+    text
+}
+
+fn to_ppm(gens: &[Pots]) -> Vec<u8> {
+    let (lo, hi) = extent(gens);
+    let width = (hi - lo + 1) as usize;
 
-        pots.push_front('.');
-        pots.push_back('.');
+    let mut ppm = format!("P6\n{} {}\n255\n", width, gens.len()).into_bytes();
+    for pots in gens {
+        for pot in lo..=hi {
+            let color = if pots.get(pot) { 255 } else { 0 };
+            ppm.extend(&[color, color, color]);
+        }
     }
+    ppm
+}
 
-    last_score
+fn solve(initial_state: &[char], automaton: &Automaton, num_gens: usize) -> isize {
+    let pots = Pots::from_bits(0, initial_state.iter().map(|&c| c == '#'));
+    simulate(automaton, &pots, num_gens).0.score()
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} FILE", env::args().next().unwrap());
+    let args = env::args().collect::<Vec<_>>();
+    let valid = match args.len() {
+        2 => true,
+        5 => args[2] == "--show",
+        6 => args[2] == "--image",
+        _ => false,
+    };
+    if !valid {
+        eprintln!(
+            "USAGE: {} FILE [--show FROM TO | --image FROM TO IMAGE]",
+            args[0]
+        );
         process::exit(1);
     }
 
-    let (initial_state, rules) = parse_input(env::args().nth(1).unwrap());
-    let part1 = solve(&initial_state, &rules, NUM_GENS_PART1);
-    let part2 = solve(&initial_state, &rules, NUM_GENS_PART2);
+    let (initial_state, rules) = parse_input(&args[1]);
+    let automaton = match Automaton::new(&rules) {
+        Ok(automaton) => automaton,
+        Err(err) => {
+            eprintln!("Invalid rules: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let missing = automaton.missing_patterns();
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: no rules for {}, assuming no plant",
+            missing.join(", ")
+        );
+    }
+
+    if args.len() > 2 {
+        let (from, to) = (args[3].parse().unwrap(), args[4].parse().unwrap());
+        let initial = Pots::from_bits(0, initial_state.iter().map(|&c| c == '#'));
+        let gens = generations(&automaton, &initial, from, to);
+        if args[2] == "--show" {
+            print!("{}", render(&gens, from));
+        } else {
+            fs::write(&args[5], to_ppm(&gens)).unwrap();
+        }
+        return;
+    }
+
+    let part1 = solve(&initial_state, &automaton, NUM_GENS_PART1);
+    let part2 = solve(&initial_state, &automaton, NUM_GENS_PART2);
     println!("Part 1: {}", part1);
     println!("Part 2: {}", part2);
 }
@@ -109,14 +375,154 @@ mod tests {
     #[test]
     fn test_puzzle_example() {
         let (initial_state, rules) = parse_input("example.txt");
-        assert_eq!(solve(&initial_state, &rules, NUM_GENS_PART1), 325);
-        assert_eq!(solve(&initial_state, &rules, NUM_GENS_PART2), 999999999374);
+        let automaton = Automaton::new(&rules).unwrap();
+        assert_eq!(solve(&initial_state, &automaton, NUM_GENS_PART1), 325);
+        assert_eq!(
+            solve(&initial_state, &automaton, NUM_GENS_PART2),
+            999999999374
+        );
     }
 
     #[test]
     fn test_puzzle_input() {
         let (initial_state, rules) = parse_input("input.txt");
-        assert_eq!(solve(&initial_state, &rules, NUM_GENS_PART1), 3241);
-        assert_eq!(solve(&initial_state, &rules, NUM_GENS_PART2), 2749999999911);
+        let automaton = Automaton::new(&rules).unwrap();
+        assert_eq!(solve(&initial_state, &automaton, NUM_GENS_PART1), 3241);
+        assert_eq!(
+            solve(&initial_state, &automaton, NUM_GENS_PART2),
+            2749999999911
+        );
+    }
+
+    #[test]
+    fn test_automaton() {
+        let (initial_state, rules) = parse_input("example.txt");
+        let automaton = Automaton::new(&rules).unwrap();
+        let mut pots = Pots::from_bits(0, initial_state.iter().map(|&c| c == '#'));
+        for _ in 0..20 {
+            pots = automaton.step(&pots);
+        }
+        let expected = "#....##....#####...#######....#.#..##";
+        assert_eq!(
+            pots,
+            Pots::from_bits(-2, expected.chars().map(|c| c == '#'))
+        );
+        assert_eq!(pots.score(), 325);
+
+        // Plants far enough apart to leave whole words of the bitset empty.
+        let bits = (0..202).map(|pot| pot == 0 || pot == 201);
+        let pots = Pots::from_bits(0, bits);
+        assert_eq!(pots.bits.len(), 4);
+        assert_eq!(pots.plants().collect::<Vec<_>>(), [0, 201]);
+
+        let (_, rules) = parse_input("input.txt");
+        let mut initial_state = vec!['#'];
+        initial_state.extend(vec!['.'; 200]);
+        initial_state.push('#');
+        let automaton = Automaton::new(&rules).unwrap();
+        assert_eq!(solve(&initial_state, &automaton, NUM_GENS_PART1), 241);
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let (initial_state, rules) = parse_input("input.txt");
+        let automaton = Automaton::new(&rules).unwrap();
+        let initial = Pots::from_bits(0, initial_state.iter().map(|&c| c == '#'));
+        let (_, cycle) = simulate(&automaton, &initial, NUM_GENS_PART2);
+        let cycle = cycle.unwrap();
+
+        for num_gens in cycle.start..cycle.start + 3 * cycle.period + 2 {
+            let mut pots = initial.clone();
+            for _ in 0..num_gens {
+                pots = automaton.step(&pots);
+            }
+            assert_eq!(simulate(&automaton, &initial, num_gens).0, pots);
+        }
+    }
+
+    #[test]
+    fn test_glider() {
+        // A single plant moving one pot to the left every generation.
+        let rules = vec![
+            ("..#..".chars().collect(), '.'),
+            ("...#.".chars().collect(), '#'),
+        ];
+        let automaton = Automaton::new(&rules).unwrap();
+        let initial = Pots::from_bits(0, vec![true]);
+        let (pots, cycle) = simulate(&automaton, &initial, 1001);
+        assert_eq!(
+            cycle,
+            Some(Cycle {
+                start: 0,
+                period: 1,
+                drift: -1
+            })
+        );
+        assert_eq!(pots.score(), -1001);
+    }
+
+    #[test]
+    fn test_rule_widths() {
+        let rule = |pattern: &str, result| (pattern.chars().collect(), result);
+
+        // Rule 90 of the elementary cellular automata draws a Sierpinski
+        // triangle, with 2^n plants after 2^n - 1 generations.
+        let rules = vec![
+            rule("###", '.'),
+            rule("##.", '#'),
+            rule("#.#", '.'),
+            rule("#..", '#'),
+            rule(".##", '#'),
+            rule(".#.", '.'),
+            rule("..#", '#'),
+            rule("...", '.'),
+        ];
+        let automaton = Automaton::new(&rules).unwrap();
+        assert!(automaton.missing_patterns().is_empty());
+        let (pots, _) = simulate(&automaton, &Pots::from_bits(0, vec![true]), 15);
+        assert_eq!(pots.plants().count(), 16);
+        // The triangle keeps growing, so its pattern never repeats.
+        let (pots, cycle) = simulate(&automaton, &Pots::from_bits(0, vec![true]), 1023);
+        assert_eq!((pots.plants().count(), cycle), (1024, None));
+
+        let (_, rules) = parse_input("example.txt");
+        let automaton = Automaton::new(&rules).unwrap();
+        assert_eq!(automaton.missing_patterns().len(), 32 - rules.len());
+        assert!(automaton
+            .missing_patterns()
+            .contains(&String::from(".....")));
+
+        assert_eq!(Automaton::new(&[]).err(), Some(RuleError::NoRules));
+        assert_eq!(
+            Automaton::new(&[rule("..", '#')]).err(),
+            Some(RuleError::InvalidWidth(2))
+        );
+        assert_eq!(
+            Automaton::new(&[rule("...", '.'), rule(".....", '.')]).err(),
+            Some(RuleError::MixedWidths(3, 5))
+        );
+        assert_eq!(
+            Automaton::new(&[rule(".....", '#')]).err(),
+            Some(RuleError::EmptyNeighbourhoodGrows)
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let (initial_state, rules) = parse_input("example.txt");
+        let automaton = Automaton::new(&rules).unwrap();
+        let initial = Pots::from_bits(0, initial_state.iter().map(|&c| c == '#'));
+        let gens = generations(&automaton, &initial, 0, 20);
+        let text = render(&gens, 0);
+        let lines = text.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2 + 21);
+        assert_eq!(lines[0], "                1         2         3");
+        assert_eq!(lines[1], "      0         0         0         0");
+        assert_eq!(lines[2], " 0: ..#..#.#..##......###...###..........");
+        assert_eq!(lines[22], "20: #....##....#####...#######....#.#..##");
+
+        let ppm = to_ppm(&gens);
+        assert!(ppm.starts_with(b"P6\n37 21\n255\n"));
     }
 }
@@ -1,24 +1,53 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::process;
+use std::str::FromStr;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Turn {
+    Left,
+    Straight,
+    Right,
+}
+
+impl FromStr for Turn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Turn::Left),
+            "straight" => Ok(Turn::Straight),
+            "right" => Ok(Turn::Right),
+            _ => Err(format!("unknown turn '{}'", s)),
+        }
+    }
+}
+
+const DEFAULT_POLICY: [Turn; 3] = [Turn::Left, Turn::Straight, Turn::Right];
+
+#[derive(Clone)]
 struct Cart {
+    id: usize,
     row: isize,
     col: isize,
     dir: char,
     intersections: usize,
+    policy: Vec<Turn>,
     crashed: bool,
 }
 
 impl Cart {
-    fn new(row: isize, col: isize, dir: char) -> Self {
+    fn new(id: usize, row: isize, col: isize, dir: char) -> Self {
         Cart {
+            id,
             row,
             col,
             dir,
             intersections: 0,
+            policy: DEFAULT_POLICY.to_vec(),
             crashed: false,
         }
     }
@@ -35,15 +64,38 @@ impl Cart {
         self.col += m.1;
     }
 
+    fn follow_track(&mut self, track: char) {
+        match track {
+            '+' => self.enter_intersection(),
+            '/' => match self.dir() {
+                '^' | 'v' => self.turn_right(),
+                '>' | '<' => self.turn_left(),
+                _ => unreachable!(),
+            },
+            '\\' => match self.dir() {
+                '^' | 'v' => self.turn_left(),
+                '>' | '<' => self.turn_right(),
+                _ => unreachable!(),
+            },
+            '-' | '|' => (),
+            _ => unreachable!(),
+        }
+    }
+
     fn enter_intersection(&mut self) {
-        match self.intersections % 3 {
-            0 => self.turn_left(),
-            2 => self.turn_right(),
-            _ => (),
+        match self.policy[self.intersections % self.policy.len()] {
+            Turn::Left => self.turn_left(),
+            Turn::Right => self.turn_right(),
+            Turn::Straight => (),
         }
         self.intersections += 1;
     }
 
+    fn set_policy(&mut self, policy: &[Turn]) {
+        assert!(!policy.is_empty());
+        self.policy = policy.to_vec();
+    }
+
     fn turn_left(&mut self) {
         self.dir = match self.dir {
             '>' => '^',
@@ -68,6 +120,10 @@ impl Cart {
         self.crashed = true;
     }
 
+    fn id(&self) -> usize {
+        self.id
+    }
+
     fn row(&self) -> isize {
         self.row
     }
@@ -85,6 +141,66 @@ impl Cart {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum PolicyError {
+    MissingColon(usize),
+    InvalidCart(usize, String),
+    InvalidTurn(usize, String),
+    EmptyPolicy(usize),
+    UnknownCart(usize),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::MissingColon(line) => write!(f, "missing ':' on line {}", line),
+            PolicyError::InvalidCart(line, id) => {
+                write!(f, "invalid cart '{}' on line {}", id, line)
+            }
+            PolicyError::InvalidTurn(line, err) => write!(f, "{} on line {}", err, line),
+            PolicyError::EmptyPolicy(line) => write!(f, "empty turn policy on line {}", line),
+            PolicyError::UnknownCart(id) => write!(f, "no cart with ID {}", id),
+        }
+    }
+}
+
+// The cart it applies to, or every cart if none, and its turns.
+type Policy = (Option<usize>, Vec<Turn>);
+
+// Each line holds a cart ID, or `*` for every cart, followed by a colon and
+// the turns the cart takes at successive intersections, e.g.
+// `*: left straight right`. Lines starting with `#` are ignored.
+fn parse_policies(file_name: impl AsRef<Path>) -> Result<Vec<Policy>, PolicyError> {
+    parse_policy_lines(&fs::read_to_string(file_name).unwrap())
+}
+
+fn parse_policy_lines(text: &str) -> Result<Vec<Policy>, PolicyError> {
+    text.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .map(|(i, l)| {
+            let line = i + 1;
+            let (cart, turns) = l.split_once(':').ok_or(PolicyError::MissingColon(line))?;
+            let cart = match cart.trim() {
+                "*" => None,
+                id => match id.parse() {
+                    Ok(id) => Some(id),
+                    Err(_) => return Err(PolicyError::InvalidCart(line, id.to_string())),
+                },
+            };
+            let policy = turns
+                .split_whitespace()
+                .map(|t| t.parse().map_err(|err| PolicyError::InvalidTurn(line, err)))
+                .collect::<Result<Vec<Turn>, _>>()?;
+            if policy.is_empty() {
+                return Err(PolicyError::EmptyPolicy(line));
+            }
+            Ok((cart, policy))
+        })
+        .collect()
+}
+
 fn parse_input(file_name: impl AsRef<Path>) -> Vec<Vec<char>> {
     fs::read_to_string(file_name)
         .unwrap()
@@ -93,117 +209,386 @@ fn parse_input(file_name: impl AsRef<Path>) -> Vec<Vec<char>> {
         .collect()
 }
 
-fn get_carts(grid: &[Vec<char>]) -> Vec<Cart> {
-    grid.iter()
-        .enumerate()
-        .flat_map(|(r, row)| {
-            row.iter()
-                .enumerate()
-                .filter(|&(_, col)| "<>^v".contains(*col))
-                .map(move |(c, col)| (r, c, *col))
-        })
-        .map(|(r, c, dir)| Cart::new(r as isize, c as isize, dir))
-        .collect()
+const NORTH: u8 = 1;
+const EAST: u8 = 2;
+const SOUTH: u8 = 4;
+const WEST: u8 = 8;
+const DIRECTIONS: [(u8, isize, isize); 4] =
+    [(NORTH, -1, 0), (EAST, 0, 1), (SOUTH, 1, 0), (WEST, 0, -1)];
+
+fn opposite(dir: u8) -> u8 {
+    (dir << 2 | dir >> 2) & 0xf
 }
 
-fn check_collisions(carts: &[Cart]) -> Option<(isize, isize)> {
-    let mut coords = HashSet::new();
-    for cart in carts {
-        if !coords.insert((cart.row(), cart.col())) {
-            return Some((cart.row(), cart.col()));
-        }
+// Directions a piece might connect to, not knowing which corner a curve is.
+fn connections(piece: char) -> u8 {
+    match piece {
+        '-' | '<' | '>' => EAST | WEST,
+        '|' | '^' | 'v' => NORTH | SOUTH,
+        '+' | '/' | '\\' => NORTH | EAST | SOUTH | WEST,
+        _ => 0,
+    }
+}
+
+// The alternative sets of directions a piece must connect to.
+fn required_connections(piece: char) -> Option<&'static [u8]> {
+    match piece {
+        '-' => Some(&[EAST | WEST]),
+        '|' => Some(&[NORTH | SOUTH]),
+        '+' => Some(&[NORTH | EAST | SOUTH | WEST]),
+        '/' => Some(&[EAST | SOUTH, WEST | NORTH]),
+        '\\' => Some(&[WEST | SOUTH, NORTH | EAST]),
+        _ => None,
+    }
+}
+
+fn piece_for(links: u8) -> Option<char> {
+    match links {
+        l if l == EAST | WEST => Some('-'),
+        l if l == NORTH | SOUTH => Some('|'),
+        l if l == NORTH | EAST | SOUTH | WEST => Some('+'),
+        l if l == EAST | SOUTH || l == WEST | NORTH => Some('/'),
+        l if l == WEST | SOUTH || l == NORTH | EAST => Some('\\'),
+        _ => None,
     }
-    None
 }
 
-fn mark_collisions(carts: &mut [Cart]) {
-    for i in 0..carts.len() {
-        let (a, b) = carts.split_at_mut(i);
-        let item_b = &mut b[0];
-        for item_a in a {
-            if item_a.row() == item_b.row() && item_a.col() == item_b.col() {
-                item_a.mark_as_crashed();
-                item_b.mark_as_crashed();
+#[derive(Debug, PartialEq, Eq)]
+enum TrackError {
+    UnknownPiece(usize, usize, char),
+    DeadEnd(usize, usize),
+    CartOffTrack(usize, usize),
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackError::UnknownPiece(r, c, piece) => {
+                write!(f, "unknown piece '{}' at {},{}", piece, c, r)
             }
+            TrackError::DeadEnd(r, c) => write!(f, "dead end at {},{}", c, r),
+            TrackError::CartOffTrack(r, c) => write!(f, "cart off track at {},{}", c, r),
         }
     }
 }
 
-fn solve_part1(grid: &[Vec<char>]) -> (isize, isize) {
-    let mut carts = get_carts(&grid);
+fn piece_at(grid: &[Vec<char>], r: isize, c: isize) -> char {
+    if r < 0 || c < 0 {
+        return ' ';
+    }
+    grid.get(r as usize)
+        .and_then(|row| row.get(c as usize))
+        .map_or(' ', |&p| p)
+}
 
-    loop {
-        carts.sort_by_key(|c| (c.row(), c.col()));
-        for i in 0..carts.len() {
-            let cart = &mut carts[i];
-            cart.do_move();
-            match grid[cart.row() as usize][cart.col() as usize] {
-                '+' => cart.enter_intersection(),
-                '/' => match cart.dir() {
-                    '^' | 'v' => cart.turn_right(),
-                    '>' | '<' => cart.turn_left(),
-                    _ => unreachable!(),
-                },
-                '\\' => match cart.dir() {
-                    '^' | 'v' => cart.turn_left(),
-                    '>' | '<' => cart.turn_right(),
-                    _ => unreachable!(),
-                },
-                '<' | '>' | '^' | 'v' | '-' | '|' => (),
-                _ => unreachable!(),
+// Whether the piece at the given position can connect in the given direction.
+// A curve only does if its other end is connected too, as that tells which
+// corner it is.
+fn connects(grid: &[Vec<char>], r: isize, c: isize, dir: u8) -> bool {
+    let piece = piece_at(grid, r, c);
+    if piece != '/' && piece != '\\' {
+        return connections(piece) & dir != 0;
+    }
+
+    let corner = required_connections(piece)
+        .unwrap()
+        .iter()
+        .find(|&&dirs| dirs & dir != 0)
+        .unwrap();
+    let &(other, dr, dc) = DIRECTIONS
+        .iter()
+        .find(|&&(d, _, _)| d == corner & !dir)
+        .unwrap();
+    connections(piece_at(grid, r + dr, c + dc)) & opposite(other) != 0
+}
+
+// Directions from the given position whose neighbour can connect back to it.
+fn links(grid: &[Vec<char>], r: usize, c: usize) -> u8 {
+    DIRECTIONS
+        .iter()
+        .filter(|&&(dir, dr, dc)| connects(grid, r as isize + dr, c as isize + dc, opposite(dir)))
+        .fold(0, |acc, &(dir, _, _)| acc | dir)
+}
+
+// Replaces each cart with the track piece beneath it, as inferred from its
+// neighbours, and checks that every track piece is connected.
+fn validate_track(grid: &[Vec<char>]) -> Result<Vec<Vec<char>>, TrackError> {
+    let mut track = grid.to_vec();
+
+    for (r, row) in grid.iter().enumerate() {
+        for (c, &piece) in row.iter().enumerate() {
+            if !" -|+/\\<>^v".contains(piece) {
+                return Err(TrackError::UnknownPiece(r, c, piece));
+            }
+        }
+    }
+
+    for (r, row) in grid.iter().enumerate() {
+        for (c, &piece) in row.iter().enumerate() {
+            let (dir, axis) = match piece {
+                '^' => (NORTH, NORTH | SOUTH),
+                'v' => (SOUTH, NORTH | SOUTH),
+                '>' => (EAST, EAST | WEST),
+                '<' => (WEST, EAST | WEST),
+                _ => continue,
+            };
+
+            // A track running alongside might look connected to the cart.
+            let mut links = links(grid, r, c);
+            if links.count_ones() == 3 && links & axis == axis {
+                links = axis;
+            }
+
+            match piece_for(links) {
+                Some(piece) if links & dir != 0 => track[r][c] = piece,
+                _ => return Err(TrackError::CartOffTrack(r, c)),
             }
+        }
+    }
 
-            if let Some(coord) = check_collisions(&carts) {
-                return coord;
+    for (r, row) in track.iter().enumerate() {
+        for (c, &piece) in row.iter().enumerate() {
+            if let Some(required) = required_connections(piece) {
+                let links = links(&track, r, c);
+                if required.iter().all(|&dirs| links & dirs != dirs) {
+                    return Err(TrackError::DeadEnd(r, c));
+                }
             }
         }
     }
+
+    Ok(track)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Collision {
+    tick: usize,
+    row: isize,
+    col: isize,
+    carts: (usize, usize),
+}
+
+#[derive(Clone)]
+struct Track {
+    grid: Vec<Vec<char>>,
+    carts: Vec<Cart>,
+    occupied: HashMap<(isize, isize), usize>,
+    ticks: usize,
 }
 
-fn solve_part2(grid: &[Vec<char>]) -> Option<(isize, isize)> {
-    let mut carts = get_carts(&grid);
+impl Track {
+    fn new(grid: &[Vec<char>]) -> Result<Self, TrackError> {
+        let track = validate_track(grid)?;
+        let carts = grid
+            .iter()
+            .enumerate()
+            .flat_map(|(r, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|&(_, col)| "<>^v".contains(*col))
+                    .map(move |(c, col)| (r, c, *col))
+            })
+            .enumerate()
+            .map(|(id, (r, c, dir))| Cart::new(id, r as isize, c as isize, dir))
+            .collect();
+
+        let mut track = Track {
+            grid: track,
+            carts,
+            occupied: HashMap::new(),
+            ticks: 0,
+        };
+        track.index_carts();
+        Ok(track)
+    }
+
+    // Maps the position of each cart to its index in `carts`.
+    fn index_carts(&mut self) {
+        self.occupied = self
+            .carts
+            .iter()
+            .enumerate()
+            .map(|(i, c)| ((c.row(), c.col()), i))
+            .collect();
+    }
+
+    // Sets the turn policy of a cart, or of every cart if no ID is given.
+    fn set_policy(&mut self, cart: Option<usize>, policy: &[Turn]) -> Result<(), PolicyError> {
+        let mut found = false;
+        for c in self.carts.iter_mut() {
+            if cart.is_none() || cart == Some(c.id()) {
+                c.set_policy(policy);
+                found = true;
+            }
+        }
+        match cart {
+            Some(id) if !found => Err(PolicyError::UnknownCart(id)),
+            _ => Ok(()),
+        }
+    }
+
+    fn carts(&self) -> &[Cart] {
+        &self.carts
+    }
+
+    #[cfg(test)]
+    fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    // Draws the carts on the track, and an 'X' where the given collisions
+    // happened.
+    fn render(&self, collisions: &[Collision]) -> String {
+        let mut grid = self.grid.clone();
+        for cart in &self.carts {
+            grid[cart.row() as usize][cart.col() as usize] = cart.dir();
+        }
+        for collision in collisions {
+            grid[collision.row as usize][collision.col as usize] = 'X';
+        }
+        grid.iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
+    }
+
+    // Moves every cart once, in order from the top-left, and returns the
+    // collisions that happened. Crashed carts are removed from the track.
+    fn tick(&mut self) -> Vec<Collision> {
+        let mut collisions = vec![];
+        self.ticks += 1;
 
-    while carts.len() > 1 {
-        carts.sort_by_key(|c| (c.row(), c.col()));
-        for i in 0..carts.len() {
-            let cart = &mut carts[i];
+        let mut order = (0..self.carts.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| (self.carts[i].row(), self.carts[i].col()));
+
+        for i in order {
+            if self.carts[i].crashed() {
+                continue;
+            }
+
+            let cart = &mut self.carts[i];
+            self.occupied.remove(&(cart.row(), cart.col()));
             cart.do_move();
-            match grid[cart.row() as usize][cart.col() as usize] {
-                '+' => cart.enter_intersection(),
-                '/' => match cart.dir() {
-                    '^' | 'v' => cart.turn_right(),
-                    '>' | '<' => cart.turn_left(),
-                    _ => unreachable!(),
-                },
-                '\\' => match cart.dir() {
-                    '^' | 'v' => cart.turn_left(),
-                    '>' | '<' => cart.turn_right(),
-                    _ => unreachable!(),
-                },
-                '<' | '>' | '^' | 'v' | '-' | '|' => (),
-                _ => unreachable!(),
+            cart.follow_track(self.grid[cart.row() as usize][cart.col() as usize]);
+
+            let (row, col) = (cart.row(), cart.col());
+            match self.occupied.remove(&(row, col)) {
+                Some(j) => {
+                    self.carts[i].mark_as_crashed();
+                    self.carts[j].mark_as_crashed();
+                    collisions.push(Collision {
+                        tick: self.ticks,
+                        row,
+                        col,
+                        carts: (self.carts[i].id(), self.carts[j].id()),
+                    });
+                }
+                None => {
+                    self.occupied.insert((row, col), i);
+                }
             }
-            mark_collisions(&mut carts);
         }
-        carts.retain(|c| !c.crashed());
+
+        if !collisions.is_empty() {
+            self.carts.retain(|c| !c.crashed());
+            self.index_carts();
+        }
+        collisions
     }
+}
 
-    if carts.len() == 1 {
-        Some((carts[0].row(), carts[0].col()))
-    } else {
-        None
+// Runs the simulation until at most one cart is left, returning all the
+// collisions in the order they happened.
+fn run(track: &mut Track) -> Vec<Collision> {
+    let mut collisions = vec![];
+    while track.carts().len() > 1 {
+        collisions.extend(track.tick());
     }
+    collisions
+}
+
+fn solve_part1(track: &Track) -> (isize, isize) {
+    let mut track = track.clone();
+    loop {
+        if let Some(collision) = track.tick().first() {
+            return (collision.row, collision.col);
+        }
+    }
+}
+
+fn solve_part2(track: &Track) -> Option<(isize, isize)> {
+    let mut track = track.clone();
+    run(&mut track);
+    track.carts().first().map(|c| (c.row(), c.col()))
 }
 
 fn main() {
-    if env::args().count() != 2 {
-        eprintln!("USAGE: {} FILE", env::args().next().unwrap());
+    let mut args = env::args().collect::<Vec<_>>();
+    let policies = match args.iter().position(|arg| arg == "--policy") {
+        Some(i) if i + 1 < args.len() => {
+            let policies = match parse_policies(&args[i + 1]) {
+                Ok(policies) => policies,
+                Err(err) => {
+                    eprintln!("Invalid policy file: {}", err);
+                    process::exit(1);
+                }
+            };
+            args.drain(i..i + 2);
+            policies
+        }
+        _ => vec![],
+    };
+
+    let valid = match args.len() {
+        2 => true,
+        3 => args[2] == "--log",
+        4 => args[2] == "--show",
+        _ => false,
+    };
+    if !valid {
+        eprintln!(
+            "USAGE: {} FILE [--policy CONFIG] [--show TICK | --log]",
+            args[0]
+        );
         process::exit(1);
     }
 
-    let grid = parse_input(env::args().nth(1).unwrap());
-    let part1 = solve_part1(&grid);
-    let part2 = solve_part2(&grid).unwrap();
+    let grid = parse_input(&args[1]);
+    let mut track = match Track::new(&grid) {
+        Ok(track) => track,
+        Err(err) => {
+            eprintln!("Invalid track: {}", err);
+            process::exit(1);
+        }
+    };
+    for (cart, policy) in &policies {
+        if let Err(err) = track.set_policy(*cart, policy) {
+            eprintln!("Invalid policy file: {}", err);
+            process::exit(1);
+        }
+    }
+
+    if args.len() == 3 {
+        for collision in run(&mut track.clone()) {
+            println!(
+                "Tick {}: carts {} and {} crashed at {},{}",
+                collision.tick, collision.carts.0, collision.carts.1, collision.col, collision.row
+            );
+        }
+        return;
+    }
+
+    if args.len() == 4 {
+        let mut track = track.clone();
+        let mut collisions = vec![];
+        for _ in 0..args[3].parse().unwrap() {
+            collisions = track.tick();
+        }
+        print!("{}", track.render(&collisions));
+        return;
+    }
+
+    let part1 = solve_part1(&track);
+    let part2 = solve_part2(&track).unwrap();
     println!("Part 1: {},{}", part1.1, part1.0);
     println!("Part 2: {},{}", part2.1, part2.0);
 }
@@ -214,20 +599,292 @@ mod tests {
 
     #[test]
     fn test_puzzle_example1() {
-        let grid = parse_input("example-part1.txt");
-        assert_eq!(solve_part1(&grid), (3, 7));
+        let track = Track::new(&parse_input("example-part1.txt")).unwrap();
+        assert_eq!(solve_part1(&track), (3, 7));
     }
 
     #[test]
     fn test_puzzle_example2() {
-        let grid = parse_input("example-part2.txt");
-        assert_eq!(solve_part2(&grid), Some((4, 6)));
+        let track = Track::new(&parse_input("example-part2.txt")).unwrap();
+        assert_eq!(solve_part2(&track), Some((4, 6)));
     }
 
     #[test]
     fn test_puzzle_input() {
-        let grid = parse_input("input.txt");
-        assert_eq!(solve_part1(&grid), (73, 65));
-        assert_eq!(solve_part2(&grid), Some((66, 54)));
+        let track = Track::new(&parse_input("input.txt")).unwrap();
+        assert_eq!(solve_part1(&track), (73, 65));
+        assert_eq!(solve_part2(&track), Some((66, 54)));
+    }
+
+    #[test]
+    fn test_collisions() {
+        let track = Track::new(&parse_input("example-part2.txt")).unwrap();
+        let mut track = track;
+        let collisions = track.tick();
+        assert_eq!(track.ticks(), 1);
+        assert_eq!(
+            collisions,
+            vec![
+                Collision {
+                    tick: 1,
+                    row: 0,
+                    col: 2,
+                    carts: (1, 0)
+                },
+                Collision {
+                    tick: 1,
+                    row: 4,
+                    col: 2,
+                    carts: (5, 4)
+                },
+                Collision {
+                    tick: 1,
+                    row: 4,
+                    col: 6,
+                    carts: (6, 3)
+                },
+            ]
+        );
+        assert_eq!(track.carts().len(), 3);
+    }
+
+    fn grid(text: &str) -> Vec<Vec<char>> {
+        text.lines().map(|l| l.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_track_beneath_carts() {
+        let track = Track::new(&grid(concat!(
+            ">---\\\n",
+            "|   |\n",
+            "| /-v-\\\n",
+            "| | | |\n",
+            "^-+-/ |\n",
+            "  ^   |\n",
+            "  \\---/\n",
+        )))
+        .unwrap();
+        assert_eq!(
+            track.grid,
+            grid(concat!(
+                "/---\\\n",
+                "|   |\n",
+                "| /-+-\\\n",
+                "| | | |\n",
+                "\\-+-/ |\n",
+                "  |   |\n",
+                "  \\---/\n",
+            ))
+        );
+        assert_eq!(track.carts().len(), 4);
+    }
+
+    #[test]
+    fn test_invalid_tracks() {
+        assert_eq!(
+            Track::new(&grid("/-\\\n| |\n\\--")).err(),
+            Some(TrackError::DeadEnd(1, 2))
+        );
+        assert_eq!(
+            Track::new(&grid("/-\\ >\n| |\n\\-/")).err(),
+            Some(TrackError::CartOffTrack(0, 4))
+        );
+        assert_eq!(
+            Track::new(&grid("/-\\\n| #\n\\-/")).err(),
+            Some(TrackError::UnknownPiece(1, 2, '#'))
+        );
+    }
+
+    #[test]
+    fn test_cart_between_curves() {
+        let mut track = Track::new(&grid(concat!(
+            " /\\\n", " \\/\n", "/->-\\\n", "| /\\|\n", "| \\/|\n", "\\---/\n",
+        )))
+        .unwrap();
+        assert_eq!(track.grid[2][2], '-');
+        for _ in 0..20 {
+            track.tick();
+        }
+        assert_eq!((track.carts()[0].row(), track.carts()[0].col()), (5, 3));
+    }
+
+    // The quadratic collision check this simulator used to have.
+    fn tick_naive(track: &mut Track) -> Vec<Collision> {
+        let mut collisions = vec![];
+        track.ticks += 1;
+        track.carts.sort_by_key(|c| (c.row(), c.col()));
+
+        for i in 0..track.carts.len() {
+            if track.carts[i].crashed() {
+                continue;
+            }
+
+            let cart = &mut track.carts[i];
+            cart.do_move();
+            cart.follow_track(track.grid[cart.row() as usize][cart.col() as usize]);
+
+            let (row, col) = (cart.row(), cart.col());
+            let other = (0..track.carts.len()).find(|&j| {
+                let other = &track.carts[j];
+                j != i && !other.crashed() && other.row() == row && other.col() == col
+            });
+
+            if let Some(j) = other {
+                track.carts[i].mark_as_crashed();
+                track.carts[j].mark_as_crashed();
+                collisions.push(Collision {
+                    tick: track.ticks,
+                    row,
+                    col,
+                    carts: (track.carts[i].id(), track.carts[j].id()),
+                });
+            }
+        }
+
+        track.carts.retain(|c| !c.crashed());
+        collisions
+    }
+
+    // Horizontal and vertical loops crossing each other, with carts scattered
+    // over their straight pieces.
+    fn crossing_loops(num_loops: usize, num_carts: usize) -> Vec<Vec<char>> {
+        let size = 4 * num_loops + 3;
+        let mut grid = vec![vec![' '; size]; size];
+
+        for i in 0..num_loops {
+            let (a, b) = (4 * i + 2, 4 * i + 4);
+            for k in 0..size {
+                for &(r, c, piece) in &[(a, k, '-'), (b, k, '-'), (k, a, '|'), (k, b, '|')] {
+                    grid[r][c] = if grid[r][c] == ' ' { piece } else { '+' };
+                }
+            }
+            grid[a + 1][0] = '|';
+            grid[a + 1][size - 1] = '|';
+            grid[0][a + 1] = '-';
+            grid[size - 1][a + 1] = '-';
+            for &(r, c, piece) in &[
+                (a, 0, '/'),
+                (b, 0, '\\'),
+                (a, size - 1, '\\'),
+                (b, size - 1, '/'),
+                (0, a, '/'),
+                (0, b, '\\'),
+                (size - 1, a, '\\'),
+                (size - 1, b, '/'),
+            ] {
+                grid[r][c] = piece;
+            }
+        }
+
+        let mut seed = 12345usize;
+        let mut placed = 0;
+        while placed < num_carts {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let (r, c) = ((seed >> 20) % size, (seed >> 40) % size);
+            let forward = (seed >> 8).is_multiple_of(2);
+            grid[r][c] = match (grid[r][c], forward) {
+                ('-', true) => '>',
+                ('-', false) => '<',
+                ('|', true) => 'v',
+                ('|', false) => '^',
+                _ => continue,
+            };
+            placed += 1;
+        }
+
+        grid
+    }
+
+    #[test]
+    fn test_many_carts() {
+        let mut track = Track::new(&crossing_loops(20, 300)).unwrap();
+        let mut naive = track.clone();
+        let mut num_collisions = 0;
+
+        for _ in 0..1000 {
+            let collisions = track.tick();
+            assert_eq!(collisions, tick_naive(&mut naive));
+            num_collisions += collisions.len();
+        }
+        assert!(num_collisions > 100);
+        assert_eq!(track.carts().len(), naive.carts().len());
+    }
+
+    #[test]
+    fn test_render_and_log() {
+        let mut track = Track::new(&parse_input("example-part1.txt")).unwrap();
+        let mut collisions = vec![];
+        while collisions.is_empty() {
+            collisions = track.tick();
+        }
+        assert_eq!(track.ticks(), 14);
+        let expected = [
+            "/---\\",
+            "|   |  /----\\",
+            "| /-+--+-\\  |",
+            "| | |  X |  |",
+            "\\-+-/  \\-+--/",
+            "  \\------/",
+        ];
+        let rendered = track.render(&collisions);
+        assert!(rendered
+            .lines()
+            .map(str::trim_end)
+            .eq(expected.iter().cloned()));
+
+        let mut track = Track::new(&parse_input("example-part2.txt")).unwrap();
+        let log = run(&mut track);
+        assert_eq!(log.len(), 4);
+        assert_eq!((log[3].tick, log[3].row, log[3].col), (3, 4, 2));
+        assert!(log.windows(2).all(|w| w[0].tick <= w[1].tick));
+    }
+
+    #[test]
+    fn test_turn_policies() {
+        let policies =
+            parse_policy_lines("# Everyone goes straight\n*: straight\n1: right left\n").unwrap();
+        assert_eq!(
+            policies,
+            vec![
+                (None, vec![Turn::Straight]),
+                (Some(1), vec![Turn::Right, Turn::Left]),
+            ]
+        );
+
+        let mut track = Track::new(&parse_input("example-part1.txt")).unwrap();
+        track.set_policy(None, &DEFAULT_POLICY).unwrap();
+        assert_eq!(solve_part1(&track), (3, 7));
+        assert_eq!(
+            track.set_policy(Some(2), &DEFAULT_POLICY),
+            Err(PolicyError::UnknownCart(2))
+        );
+
+        for (cart, policy) in &policies {
+            track.set_policy(*cart, policy).unwrap();
+        }
+        assert_eq!(track.carts()[0].policy, vec![Turn::Straight]);
+        assert_eq!(track.carts()[1].policy, vec![Turn::Right, Turn::Left]);
+        let first_crash = (0..100).find_map(|_| {
+            let collisions = track.tick();
+            collisions.first().map(|c| (c.tick, c.row, c.col))
+        });
+        assert_eq!(first_crash, Some((14, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_policies() {
+        use PolicyError::*;
+        assert_eq!(parse_policy_lines("* left"), Err(MissingColon(1)));
+        assert_eq!(
+            parse_policy_lines("# carts\n\nx: left"),
+            Err(InvalidCart(3, "x".to_string()))
+        );
+        assert_eq!(
+            parse_policy_lines("0: left up"),
+            Err(InvalidTurn(1, "unknown turn 'up'".to_string()))
+        );
+        assert_eq!(parse_policy_lines("*: left\n1:"), Err(EmptyPolicy(2)));
     }
 }
@@ -1,10 +1,10 @@
 use std::env;
 use std::process;
 
-fn digits(mut num: usize) -> Vec<usize> {
+fn digits(mut num: usize) -> Vec<u8> {
     let mut digits = vec![];
     loop {
-        digits.push(num % 10);
+        digits.push((num % 10) as u8);
         num /= 10;
         if num == 0 {
             break;
@@ -14,19 +14,91 @@ fn digits(mut num: usize) -> Vec<usize> {
     digits
 }
 
-fn solve_part1(num_recipes: usize) -> String {
-    let mut scoreboard = vec![3, 7];
-    let (mut elf1, mut elf2) = (0, 1);
-
-    while scoreboard.len() < num_recipes + 10 {
-        let new_recipes = digits(scoreboard[elf1] + scoreboard[elf2]);
-        scoreboard.extend(new_recipes.iter());
-        elf1 = (elf1 + 1 + scoreboard[elf1]) % scoreboard.len();
-        elf2 = (elf2 + 1 + scoreboard[elf2]) % scoreboard.len();
+// Recipe scoreboard that yields every score in order, creating new
+// recipes only as they are needed. Scores are single digits, so they are
+// stored one per byte.
+struct Scoreboard {
+    recipes: Vec<u8>,
+    elf1: usize,
+    elf2: usize,
+    next: usize,
+}
+
+impl Scoreboard {
+    fn new() -> Scoreboard {
+        Scoreboard {
+            recipes: vec![3, 7],
+            elf1: 0,
+            elf2: 1,
+            next: 0,
+        }
+    }
+
+    fn combine(&mut self) {
+        let sum = self.recipes[self.elf1] + self.recipes[self.elf2];
+        if sum >= 10 {
+            self.recipes.push(sum / 10);
+        }
+        self.recipes.push(sum % 10);
+        let len = self.recipes.len();
+        self.elf1 = (self.elf1 + 1 + self.recipes[self.elf1] as usize) % len;
+        self.elf2 = (self.elf2 + 1 + self.recipes[self.elf2] as usize) % len;
+    }
+}
+
+impl Iterator for Scoreboard {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.next == self.recipes.len() {
+            self.combine();
+        }
+        self.next += 1;
+        Some(self.recipes[self.next - 1])
+    }
+}
+
+// KMP failure table: for each prefix of `pattern`, the length of its
+// longest proper prefix that is also a suffix.
+fn failure_table(pattern: &[u8]) -> Vec<usize> {
+    let mut table = vec![0; pattern.len()];
+    let mut k = 0;
+    for i in 1..pattern.len() {
+        while k > 0 && pattern[i] != pattern[k] {
+            k = table[k - 1];
+        }
+        if pattern[i] == pattern[k] {
+            k += 1;
+        }
+        table[i] = k;
     }
+    table
+}
 
-    scoreboard
-        .iter()
+// Returns the position of the first occurrence of `pattern` in `scores`,
+// matching one score at a time without looking back at earlier scores.
+fn find_pattern(scores: impl Iterator<Item = u8>, pattern: &[u8]) -> Option<usize> {
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let table = failure_table(pattern);
+    let mut matched = 0;
+    for (i, score) in scores.enumerate() {
+        while matched > 0 && score != pattern[matched] {
+            matched = table[matched - 1];
+        }
+        if score == pattern[matched] {
+            matched += 1;
+        }
+        if matched == pattern.len() {
+            return Some(i + 1 - pattern.len());
+        }
+    }
+    None
+}
+
+fn solve_part1(num_recipes: usize) -> String {
+    Scoreboard::new()
         .skip(num_recipes)
         .take(10)
         .map(|x| x.to_string())
@@ -34,23 +106,7 @@ fn solve_part1(num_recipes: usize) -> String {
 }
 
 fn solve_part2(target_score: usize) -> usize {
-    let mut scoreboard = vec![3, 7];
-    let (mut elf1, mut elf2) = (0, 1);
-    let target_score = digits(target_score);
-
-    loop {
-        let new_recipes = digits(scoreboard[elf1] + scoreboard[elf2]);
-        scoreboard.extend(new_recipes.iter());
-        elf1 = (elf1 + 1 + scoreboard[elf1]) % scoreboard.len();
-        elf2 = (elf2 + 1 + scoreboard[elf2]) % scoreboard.len();
-
-        for i in 0..new_recipes.len() {
-            let idx = scoreboard.len() as isize - target_score.len() as isize - i as isize;
-            if idx >= 0 && scoreboard[idx as usize..scoreboard.len() - i] == target_score[..] {
-                return idx as usize;
-            }
-        }
-    }
+    find_pattern(Scoreboard::new(), &digits(target_score)).unwrap()
 }
 
 fn main() {
@@ -70,6 +126,39 @@ fn main() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_scoreboard() {
+        let scores: Vec<u8> = Scoreboard::new().take(20).collect();
+        assert_eq!(
+            scores,
+            [3, 7, 1, 0, 1, 0, 1, 2, 4, 5, 1, 5, 8, 9, 1, 6, 7, 7, 9, 2]
+        );
+    }
+
+    #[test]
+    fn test_examples() {
+        assert_eq!(solve_part1(9), "5158916779");
+        assert_eq!(solve_part1(2018), "5941429882");
+        assert_eq!(find_pattern(Scoreboard::new(), &[5, 1, 5, 8, 9]), Some(9));
+        assert_eq!(find_pattern(Scoreboard::new(), &[0, 1, 2, 4, 5]), Some(5));
+        assert_eq!(
+            find_pattern(Scoreboard::new(), &[5, 9, 4, 1, 4]),
+            Some(2018)
+        );
+    }
+
+    #[test]
+    fn test_find_pattern() {
+        assert_eq!(failure_table(&[1, 1, 2, 1, 1, 1]), [0, 1, 0, 1, 2, 2]);
+        let text = [1, 1, 1, 2, 1, 1, 1, 1, 2];
+        assert_eq!(find_pattern(text.iter().cloned(), &[1, 1, 1, 2]), Some(0));
+        assert_eq!(
+            find_pattern(text.iter().cloned(), &[1, 1, 2, 1, 1, 1, 1]),
+            Some(1)
+        );
+        assert_eq!(find_pattern(text.iter().cloned(), &[2, 2]), None);
+    }
+
     #[test]
     fn test_puzzle_input() {
         assert_eq!(solve_part1(702831), "1132413111");